@@ -1,6 +1,126 @@
 use xcb::x;
 
+use crate::tiling::Rect;
+
+// WM_SIZE_HINTS flags
+const P_MIN_SIZE: u32 = 1 << 4;
+const P_MAX_SIZE: u32 = 1 << 5;
+const P_RESIZE_INC: u32 = 1 << 6;
+const P_ASPECT: u32 = 1 << 7;
+const P_BASE_SIZE: u32 = 1 << 8;
+
+/// ICCCM WM_NORMAL_HINTS of a client, zero means unset.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct SizeHints {
+    pub min_width: u32,
+    pub min_height: u32,
+    pub max_width: u32,
+    pub max_height: u32,
+    pub width_inc: u32,
+    pub height_inc: u32,
+    pub base_width: u32,
+    pub base_height: u32,
+    // height / width
+    pub min_aspect: f32,
+    // width / height
+    pub max_aspect: f32,
+}
+
+impl SizeHints {
+    pub fn from_property(data: &[u32]) -> Self {
+        let mut hints = SizeHints::default();
+        // pre-ICCCM clients set only 15 fields, without base size and gravity
+        if data.len() < 15 {
+            return hints;
+        }
+        let flags = data[0];
+        let has_base_size = flags & P_BASE_SIZE != 0 && data.len() >= 17;
+
+        if has_base_size {
+            hints.base_width = data[15];
+            hints.base_height = data[16];
+        }
+        if flags & P_MIN_SIZE != 0 {
+            hints.min_width = data[5];
+            hints.min_height = data[6];
+        }
+        // base and min size default to each other
+        if !has_base_size {
+            hints.base_width = hints.min_width;
+            hints.base_height = hints.min_height;
+        }
+        if flags & P_MIN_SIZE == 0 {
+            hints.min_width = hints.base_width;
+            hints.min_height = hints.base_height;
+        }
+        if flags & P_MAX_SIZE != 0 {
+            hints.max_width = data[7];
+            hints.max_height = data[8];
+        }
+        if flags & P_RESIZE_INC != 0 {
+            hints.width_inc = data[9];
+            hints.height_inc = data[10];
+        }
+        if flags & P_ASPECT != 0 && data[11] != 0 && data[14] != 0 {
+            hints.min_aspect = data[12] as f32 / data[11] as f32;
+            hints.max_aspect = data[13] as f32 / data[14] as f32;
+        }
+        hints
+    }
+
+    pub fn is_fixed(&self) -> bool {
+        self.max_width != 0 && self.max_width == self.min_width && self.max_height != 0 && self.max_height == self.min_height
+    }
+
+    /// Shrinks the size to the closest one allowed by the hints, in the same way as dwm does.
+    pub fn apply(&self, width: u32, height: u32, honor_increments: bool) -> (u32, u32) {
+        let mut width = width as f32;
+        let mut height = height as f32;
+        let base_width = self.base_width as f32;
+        let base_height = self.base_height as f32;
+
+        // the base size must not be counted for the aspect ratio, unless it is also the min size
+        let base_is_min = self.base_width == self.min_width && self.base_height == self.min_height;
+        if !base_is_min {
+            width -= base_width;
+            height -= base_height;
+        }
+        if self.min_aspect > 0.0 && self.max_aspect > 0.0 && height > 0.0 && width > 0.0 {
+            if self.max_aspect < width / height {
+                width = (height * self.max_aspect + 0.5).floor();
+            }
+            else if self.min_aspect < height / width {
+                height = (width * self.min_aspect + 0.5).floor();
+            }
+        }
+        if base_is_min {
+            width -= base_width;
+            height -= base_height;
+        }
+
+        if honor_increments {
+            if self.width_inc > 0 {
+                width -= width.max(0.0) % self.width_inc as f32;
+            }
+            if self.height_inc > 0 {
+                height -= height.max(0.0) % self.height_inc as f32;
+            }
+        }
+
+        let mut width = ((width + base_width).max(self.min_width as f32)).max(1.0) as u32;
+        let mut height = ((height + base_height).max(self.min_height as f32)).max(1.0) as u32;
+        if self.max_width > 0 {
+            width = width.min(self.max_width);
+        }
+        if self.max_height > 0 {
+            height = height.min(self.max_height);
+        }
+        (width, height)
+    }
+}
+
 #[derive(Debug)]
+#[allow(non_snake_case)]
 pub struct Client {
     pub posX: i16,
     pub posY: i16,
@@ -8,9 +128,20 @@ pub struct Client {
     pub width: u16,
     pub window: x::Window,
     pub reconfigure: bool,
+    // number of UnmapNotify events caused by dswm hiding the window
+    pub ignore_unmaps: u32,
+    // bitmask of the workspaces the client is shown on
+    pub tags: u32,
+    pub floating: bool,
+    // geometry of the window itself in root coordinates, only used while floating
+    pub float_rect: Rect,
+    pub size_hints: SizeHints,
+    // covers the whole monitor, the floating state and float_rect are kept to restore it afterwards
+    pub fullscreen: bool,
 }
 
 impl Client {
+    #[allow(non_snake_case)]
     pub fn new(window: x::Window, posX: i16, posY: i16, height: u16, width: u16) -> Self {
         Client {
             posX,
@@ -19,9 +150,24 @@ impl Client {
             width,
             window,
             reconfigure: false,
+            ignore_unmaps: 0,
+            tags: 0,
+            floating: false,
+            float_rect: Rect::new(posX, posY, width, height),
+            size_hints: SizeHints::default(),
+            fullscreen: false,
         }
     }
 
+    pub fn is_visible(&self, viewed_tags: u32) -> bool {
+        self.tags & viewed_tags != 0
+    }
+
+    pub fn is_tiled(&self, viewed_tags: u32) -> bool {
+        self.is_visible(viewed_tags) && !self.floating && !self.fullscreen
+    }
+
+    #[allow(non_snake_case)]
     pub fn set_pos(&mut self, posX: i16, posY: i16) {
         self.posX = posX;
         self.posY = posY;
@@ -34,3 +180,73 @@ impl Client {
         self.reconfigure = true;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hints(flags: u32, min: (u32, u32), max: (u32, u32), inc: (u32, u32), aspect: [u32; 4], base: (u32, u32)) -> Vec<u32> {
+        vec![
+            flags, 0, 0, 0, 0,
+            min.0, min.1, max.0, max.1, inc.0, inc.1,
+            aspect[0], aspect[1], aspect[2], aspect[3],
+            base.0, base.1, 0,
+        ]
+    }
+
+    #[test]
+    fn parses_size_hints() {
+        let data = hints(P_MIN_SIZE | P_MAX_SIZE | P_RESIZE_INC | P_BASE_SIZE, (100, 50), (800, 600), (10, 20), [0; 4], (4, 6));
+        let hints = SizeHints::from_property(&data);
+        assert_eq!((hints.min_width, hints.min_height), (100, 50));
+        assert_eq!((hints.max_width, hints.max_height), (800, 600));
+        assert_eq!((hints.width_inc, hints.height_inc), (10, 20));
+        assert_eq!((hints.base_width, hints.base_height), (4, 6));
+    }
+
+    #[test]
+    fn base_and_min_size_default_to_each_other() {
+        let min_only = SizeHints::from_property(&hints(P_MIN_SIZE, (100, 50), (0, 0), (0, 0), [0; 4], (4, 6)));
+        assert_eq!((min_only.base_width, min_only.base_height), (100, 50));
+        let base_only = SizeHints::from_property(&hints(P_BASE_SIZE, (100, 50), (0, 0), (0, 0), [0; 4], (4, 6)));
+        assert_eq!((base_only.min_width, base_only.min_height), (4, 6));
+    }
+
+    #[test]
+    fn accepts_pre_icccm_hints() {
+        let mut data = hints(P_MIN_SIZE | P_BASE_SIZE, (100, 50), (0, 0), (0, 0), [0; 4], (4, 6));
+        data.truncate(15);
+        let hints = SizeHints::from_property(&data);
+        assert_eq!((hints.min_width, hints.min_height), (100, 50));
+        // the base size is missing, so it falls back to the min size
+        assert_eq!((hints.base_width, hints.base_height), (100, 50));
+
+        assert_eq!(SizeHints::from_property(&data[..14]), SizeHints::default());
+    }
+
+    #[test]
+    fn apply_min_max() {
+        let hints = SizeHints::from_property(&hints(P_MIN_SIZE | P_MAX_SIZE, (100, 50), (800, 600), (0, 0), [0; 4], (0, 0)));
+        assert_eq!(hints.apply(20, 10, true), (100, 50));
+        assert_eq!(hints.apply(1000, 1000, true), (800, 600));
+        assert_eq!(hints.apply(300, 200, true), (300, 200));
+        assert_eq!(SizeHints::default().apply(0, 0, true), (1, 1));
+    }
+
+    #[test]
+    fn apply_increments() {
+        let hints = SizeHints::from_property(&hints(P_RESIZE_INC | P_BASE_SIZE, (0, 0), (0, 0), (10, 20), [0; 4], (4, 6)));
+        // only the part above the base size is rounded down to the increments
+        assert_eq!(hints.apply(108, 100, true), (104, 86));
+        assert_eq!(hints.apply(108, 100, false), (108, 100));
+    }
+
+    #[test]
+    fn apply_aspect() {
+        // width / height between 1/2 and 2/1
+        let hints = SizeHints::from_property(&hints(P_ASPECT, (0, 0), (0, 0), (0, 0), [1, 2, 2, 1], (0, 0)));
+        assert_eq!(hints.apply(1000, 100, true), (200, 100));
+        assert_eq!(hints.apply(100, 1000, true), (100, 200));
+        assert_eq!(hints.apply(300, 200, true), (300, 200));
+    }
+}
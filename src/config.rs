@@ -1,32 +1,97 @@
 use std::{collections::HashMap, process};
+use std::time::Duration;
 
-use config::{Config, ConfigError, File, ValueKind};
-use log::{info, error};
+use config::{Config, ConfigError, File, Value, ValueKind};
+use log::error;
 
 use strum_macros::EnumString;
 use std::str::FromStr;
 
-#[derive(Debug, EnumString, PartialEq, Eq, Hash)]
-enum Actions {
+#[allow(non_camel_case_types)]
+#[derive(Debug, EnumString, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Actions {
     quit,
+    restart,
+    close,
     focus,
+    focus_monitor,
+    send_to_monitor,
+    view_workspace,
+    toggle_view_workspace,
+    move_to_workspace,
+    toggle_client_workspace,
+    set_layout,
+    change_master_factor,
+    change_master_count,
+    toggle_floating,
 }
 
+/// Which _NET_ACTIVE_WINDOW requests may switch workspaces and take the focus.
+#[allow(non_camel_case_types)]
+#[derive(Debug, EnumString, PartialEq, Eq, Clone, Copy)]
+pub enum FocusStealing {
+    // honor every request
+    allow,
+    // requests from applications only focus clients that are already visible
+    pager,
+    // requests from applications are ignored
+    deny,
+}
+
+/// How the pointer moves the focus between clients.
+#[allow(non_camel_case_types)]
+#[derive(Debug, EnumString, PartialEq, Eq, Clone, Copy)]
+pub enum FocusModel {
+    // the focus follows the pointer into a client and stays when it leaves to the root
+    sloppy,
+    // clicking a client focuses it
+    click,
+    // only key bindings and requests change the focus
+    none,
+}
+
+#[allow(non_camel_case_types)]
 #[derive(Debug, PartialEq, Eq, Hash)]
-struct keymapArgs {
-    action: Actions,
-    args: Option<Vec<String>>,
+pub struct keymapArgs {
+    pub action: Actions,
+    pub args: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Appearance {
+    pub inner_gap: u16,
+    pub outer_gap: u16,
+    pub border_width: u16,
+    pub border_color_focused: u32,
+    pub border_color_unfocused: u32,
+    // drop gaps and borders when only one client is visible
+    pub smart_gaps: bool,
+    pub smart_borders: bool,
+    // tiled clients fill their whole cell instead of snapping to their resize increments
+    pub ignore_tiled_size_increments: bool,
 }
 
 #[derive(Debug)]
 #[allow(unused)]
 pub struct Settings {
-    keymap: HashMap<String, keymapArgs>,
+    pub keymap: HashMap<String, keymapArgs>,
+    pub workspaces: Vec<String>,
+    pub layout: String,
+    pub master_factor: f32,
+    pub master_count: usize,
+    pub appearance: Appearance,
+    pub mouse_modifier: String,
+    pub focus_stealing: FocusStealing,
+    pub focus_model: FocusModel,
+    // move the pointer into clients focused without the mouse
+    pub warp_pointer: bool,
+    // milliseconds a client gets to close its window before it is killed, 0 waits forever
+    pub close_timeout: u64,
 }
 
 impl Settings {
     pub fn new() -> Result<Self, ConfigError> {
-        let home = std::env::var_os("HOME").unwrap().into_string().unwrap_or_else(|err| {
+        let home = std::env::var_os("HOME").unwrap().into_string().unwrap_or_else(|_| {
             error!("config error: unable to finde $HOME");
             process::exit(1);
         });
@@ -35,28 +100,110 @@ impl Settings {
             process::exit(1);
         });
         let keymap_table = s.get_table("keymap").unwrap();
+        let mut workspaces = match s.get_array("workspaces") {
+            Ok(names) => names.into_iter().map(|x| x.into_string()).collect::<Result<Vec<_>, _>>()?,
+            Err(_) => (1..=9).map(|i| i.to_string()).collect(),
+        };
+        // workspaces are stored as a bitmask on each client
+        if workspaces.len() > 32 {
+            error!("config error: at most 32 workspaces are supported");
+            workspaces.truncate(32);
+        }
+        if workspaces.is_empty() {
+            error!("config error: at least one workspace is required");
+            workspaces.push("1".to_owned());
+        }
+        let layout = s.get_string("layout").unwrap_or("master_stack_left".to_owned());
+        let master_factor = s.get_float("master_factor").unwrap_or(0.5).clamp(0.1, 0.9) as f32;
+        let master_count = s.get_int("master_count").unwrap_or(1).max(0) as usize;
+        let appearance = Appearance {
+            inner_gap: s.get_int("inner_gap").unwrap_or(0).clamp(0, u16::MAX as i64) as u16,
+            outer_gap: s.get_int("outer_gap").unwrap_or(0).clamp(0, u16::MAX as i64) as u16,
+            border_width: s.get_int("border_width").unwrap_or(0).clamp(0, u16::MAX as i64) as u16,
+            border_color_focused: Self::get_color(&s, "border_color_focused", 0x005577),
+            border_color_unfocused: Self::get_color(&s, "border_color_unfocused", 0x444444),
+            smart_gaps: s.get_bool("smart_gaps").unwrap_or(false),
+            smart_borders: s.get_bool("smart_borders").unwrap_or(false),
+            ignore_tiled_size_increments: s.get_bool("ignore_tiled_size_increments").unwrap_or(false),
+        };
         let mut settings = Settings {
             keymap: HashMap::new(),
+            workspaces,
+            layout,
+            master_factor,
+            master_count,
+            appearance,
+            mouse_modifier: s.get_string("mouse_modifier").unwrap_or("Mod4".to_owned()),
+            focus_stealing: Self::get_focus_stealing(&s),
+            focus_model: Self::get_focus_model(&s),
+            warp_pointer: s.get_bool("warp_pointer").unwrap_or(false),
+            close_timeout: s.get_int("close_timeout").unwrap_or(0).max(0) as u64,
         };
         for (key, val) in keymap_table {
             let action = Actions::from_str(&key).unwrap();
-            match val.kind {
-                ValueKind::String(str) => {
-                    settings.keymap.insert(str, keymapArgs{ action, args: Option::None, });
-                }
-                ValueKind::Table(mut table) => {
-                    let str = table.remove("key").unwrap().into_string()?;
-                    let args = table.remove("args").unwrap().into_array()?.into_iter().map(|x|
-                        x.into_string()
-                    ).collect::<Result<Vec<_>, _>>()?;
-                    settings.keymap.insert(str, keymapArgs{ action, args: Option::Some(args), });
+            Self::insert_keymap_entry(&mut settings.keymap, action, val)?;
+        }
 
+        Result::Ok(settings)
+    }
+
+    pub fn get_close_timeout(&self) -> Option<Duration> {
+        (self.close_timeout > 0).then(|| Duration::from_millis(self.close_timeout))
+    }
+
+    fn get_focus_stealing(s: &Config) -> FocusStealing {
+        let Ok(policy) = s.get_string("focus_stealing") else {
+            return FocusStealing::allow;
+        };
+        FocusStealing::from_str(&policy).unwrap_or_else(|_| {
+            error!("config error: unknown focus_stealing policy \"{}\"", policy);
+            FocusStealing::allow
+        })
+    }
+
+    fn get_focus_model(s: &Config) -> FocusModel {
+        let Ok(model) = s.get_string("focus_model") else {
+            return FocusModel::sloppy;
+        };
+        FocusModel::from_str(&model).unwrap_or_else(|_| {
+            error!("config error: unknown focus_model \"{}\"", model);
+            FocusModel::sloppy
+        })
+    }
+
+    // colors are written as "#rrggbb"
+    fn get_color(s: &Config, key: &str, default: u32) -> u32 {
+        let Ok(color) = s.get_string(key) else {
+            return default;
+        };
+        u32::from_str_radix(color.trim_start_matches('#'), 16).unwrap_or_else(|_| {
+            error!("config error: invalid color {} = \"{}\"", key, color);
+            default
+        })
+    }
+
+    fn insert_keymap_entry(keymap: &mut HashMap<String, keymapArgs>, action: Actions, val: Value) -> Result<(), ConfigError> {
+        match val.kind {
+            ValueKind::String(str) => {
+                keymap.insert(str, keymapArgs{ action, args: Option::None, });
+            }
+            ValueKind::Table(mut table) => {
+                let str = table.remove("key").unwrap().into_string()?;
+                let args = table.remove("args").unwrap().into_array()?.into_iter().map(|x|
+                    x.into_string()
+                ).collect::<Result<Vec<_>, _>>()?;
+                keymap.insert(str, keymapArgs{ action, args: Option::Some(args), });
+
+            }
+            // the same action bound to multiple keys, e.g. one per workspace
+            ValueKind::Array(entries) => {
+                for entry in entries {
+                    Self::insert_keymap_entry(keymap, action, entry)?;
                 }
-                _ => {}
-                
             }
+            _ => {}
+            
         }
-
-        Result::Ok(settings)
+        Ok(())
     }
 }
@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+
+use log::{debug, error, warn};
+use xcb::x;
+
+use crate::config::{Actions, keymapArgs};
+use crate::xmanager::Xmanager;
+
+#[derive(Debug)]
+pub struct KeyBinding {
+    modifiers: x::ModMask,
+    keysym: x::Keysym,
+    pub action: Actions,
+    pub args: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseAction {
+    Move,
+    Resize,
+}
+
+pub struct KeyBindings {
+    bindings: Vec<KeyBinding>,
+    grabbed: HashMap<(x::Keycode, x::ModMask), usize>,
+    mouse_modifier: x::ModMask,
+    // the modifier NumLock is mapped to, looked up whenever the keys are grabbed
+    numlock_mask: x::ModMask,
+}
+
+impl KeyBindings {
+    pub fn new(keymap: &HashMap<String, keymapArgs>, mouse_modifier: &str) -> Self {
+        let mut bindings = Vec::new();
+        for (key, keymap_args) in keymap {
+            match parse_key(key) {
+                Some((modifiers, keysym)) => {
+                    bindings.push(KeyBinding {
+                        modifiers,
+                        keysym,
+                        action: keymap_args.action,
+                        args: keymap_args.args.clone(),
+                    });
+                }
+                None => {
+                    error!("config error: unable to parse key \"{}\"", key);
+                }
+            }
+        }
+
+        let mouse_modifier = modifier_from_name(mouse_modifier).unwrap_or_else(|| {
+            error!("config error: unknown mouse modifier \"{}\"", mouse_modifier);
+            x::ModMask::N4
+        });
+
+        KeyBindings {
+            bindings,
+            grabbed: HashMap::new(),
+            mouse_modifier,
+            numlock_mask: x::ModMask::empty(),
+        }
+    }
+
+    /// Grabs the keys of all bindings, needs to be called again whenever the keyboard mapping changes.
+    pub fn grab_keys(&mut self, xmanager: &Xmanager) {
+        xmanager.ungrab_all_keys();
+        self.grabbed.clear();
+        self.numlock_mask = xmanager.get_numlock_mask();
+
+        for (i, binding) in self.bindings.iter().enumerate() {
+            let keycode = match xmanager.keycode_from_keysym(binding.keysym) {
+                Some(keycode) => keycode,
+                None => {
+                    warn!("no keycode for keysym {:#x}, binding {:?} not grabbed", binding.keysym, binding.action);
+                    continue;
+                }
+            };
+            for ignored in self.get_ignored_modifiers() {
+                xmanager.grab_key(keycode, binding.modifiers | ignored);
+            }
+            debug!("grabbed {:?} for {:?}", (keycode, binding.modifiers), binding.action);
+            self.grabbed.insert((keycode, binding.modifiers), i);
+        }
+    }
+
+    pub fn get_binding(&self, keycode: x::Keycode, state: x::KeyButMask) -> Option<&KeyBinding> {
+        let modifiers = self.clean_modifiers(state);
+        self.grabbed.get(&(keycode, modifiers)).map(|i| &self.bindings[*i])
+    }
+
+    /// Grabs mouse modifier + left button for moving and mouse modifier + right button for resizing windows.
+    /// Uses the NumLock modifier found by grab_keys, so it has to be called after it.
+    pub fn grab_buttons(&self, xmanager: &Xmanager) {
+        xmanager.ungrab_all_buttons();
+        for button in [x::ButtonIndex::N1, x::ButtonIndex::N3] {
+            for ignored in self.get_ignored_modifiers() {
+                xmanager.grab_button(button, self.mouse_modifier | ignored);
+            }
+        }
+    }
+
+    pub fn get_mouse_action(&self, button: x::Button, state: x::KeyButMask) -> Option<MouseAction> {
+        if self.clean_modifiers(state) != self.mouse_modifier {
+            return None;
+        }
+        match button {
+            1 => Some(MouseAction::Move),
+            3 => Some(MouseAction::Resize),
+            _ => None,
+        }
+    }
+
+    // lock modifiers that should not affect whether a binding matches
+    fn get_ignored_modifiers(&self) -> [x::ModMask; 4] {
+        [x::ModMask::empty(), x::ModMask::LOCK, self.numlock_mask, x::ModMask::LOCK | self.numlock_mask]
+    }
+
+    fn clean_modifiers(&self, state: x::KeyButMask) -> x::ModMask {
+        let mut modifiers = x::ModMask::from_bits_truncate(state.bits());
+        modifiers.remove(x::ModMask::LOCK | self.numlock_mask);
+        modifiers
+    }
+}
+
+/// Parses a key string like "Mod4+Shift+q" into a modifier mask and a keysym.
+fn parse_key(key: &str) -> Option<(x::ModMask, x::Keysym)> {
+    let mut parts: Vec<&str> = key.split('+').map(|part| part.trim()).collect();
+    let keysym = keysym_from_name(parts.pop()?)?;
+
+    let mut modifiers = x::ModMask::empty();
+    for part in parts {
+        modifiers |= modifier_from_name(part)?;
+    }
+    Some((modifiers, keysym))
+}
+
+fn modifier_from_name(name: &str) -> Option<x::ModMask> {
+    match name.to_lowercase().as_str() {
+        "shift" => Some(x::ModMask::SHIFT),
+        "lock" => Some(x::ModMask::LOCK),
+        "control" | "ctrl" => Some(x::ModMask::CONTROL),
+        "mod1" | "alt" => Some(x::ModMask::N1),
+        "mod2" => Some(x::ModMask::N2),
+        "mod3" => Some(x::ModMask::N3),
+        "mod4" | "super" => Some(x::ModMask::N4),
+        "mod5" => Some(x::ModMask::N5),
+        _ => None,
+    }
+}
+
+/// Supports single latin1 characters, F1 to F35, raw keysyms like "0xff61" and the keysymdef.h names below.
+/// Other names are reported as config errors by the caller.
+fn keysym_from_name(name: &str) -> Option<x::Keysym> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        // latin1 keysyms are identical to their code point, letters are bound by their lowercase keysym
+        if c.is_ascii_graphic() {
+            return Some(c.to_ascii_lowercase() as x::Keysym);
+        }
+    }
+
+    if let Some(n) = name.strip_prefix('F').and_then(|n| n.parse::<u32>().ok()) {
+        if (1..=35).contains(&n) {
+            return Some(0xffbe + n - 1);
+        }
+    }
+
+    if let Some(hex) = name.strip_prefix("0x") {
+        return x::Keysym::from_str_radix(hex, 16).ok();
+    }
+
+    let keysym = match name {
+        "space" => 0x0020,
+        "exclam" => 0x0021,
+        "quotedbl" => 0x0022,
+        "numbersign" => 0x0023,
+        "dollar" => 0x0024,
+        "percent" => 0x0025,
+        "ampersand" => 0x0026,
+        "apostrophe" => 0x0027,
+        "parenleft" => 0x0028,
+        "parenright" => 0x0029,
+        "asterisk" => 0x002a,
+        "plus" => 0x002b,
+        "comma" => 0x002c,
+        "minus" => 0x002d,
+        "period" => 0x002e,
+        "slash" => 0x002f,
+        "colon" => 0x003a,
+        "semicolon" => 0x003b,
+        "less" => 0x003c,
+        "equal" => 0x003d,
+        "greater" => 0x003e,
+        "question" => 0x003f,
+        "at" => 0x0040,
+        "bracketleft" => 0x005b,
+        "backslash" => 0x005c,
+        "bracketright" => 0x005d,
+        "asciicircum" => 0x005e,
+        "underscore" => 0x005f,
+        "grave" => 0x0060,
+        "braceleft" => 0x007b,
+        "bar" => 0x007c,
+        "braceright" => 0x007d,
+        "asciitilde" => 0x007e,
+        "BackSpace" => 0xff08,
+        "Tab" => 0xff09,
+        "Linefeed" => 0xff0a,
+        "Clear" => 0xff0b,
+        "Return" => 0xff0d,
+        "Pause" => 0xff13,
+        "Scroll_Lock" => 0xff14,
+        "Sys_Req" => 0xff15,
+        "Escape" => 0xff1b,
+        "Home" => 0xff50,
+        "Left" => 0xff51,
+        "Up" => 0xff52,
+        "Right" => 0xff53,
+        "Down" => 0xff54,
+        "Page_Up" | "Prior" => 0xff55,
+        "Page_Down" | "Next" => 0xff56,
+        "End" => 0xff57,
+        "Begin" => 0xff58,
+        "Select" => 0xff60,
+        "Print" => 0xff61,
+        "Execute" => 0xff62,
+        "Insert" => 0xff63,
+        "Undo" => 0xff65,
+        "Redo" => 0xff66,
+        "Menu" => 0xff67,
+        "Find" => 0xff68,
+        "Cancel" => 0xff69,
+        "Help" => 0xff6a,
+        "Break" => 0xff6b,
+        "Mode_switch" => 0xff7e,
+        "Num_Lock" => 0xff7f,
+        "KP_Space" => 0xff80,
+        "KP_Tab" => 0xff89,
+        "KP_Enter" => 0xff8d,
+        "KP_F1" => 0xff91,
+        "KP_F2" => 0xff92,
+        "KP_F3" => 0xff93,
+        "KP_F4" => 0xff94,
+        "KP_Home" => 0xff95,
+        "KP_Left" => 0xff96,
+        "KP_Up" => 0xff97,
+        "KP_Right" => 0xff98,
+        "KP_Down" => 0xff99,
+        "KP_Page_Up" | "KP_Prior" => 0xff9a,
+        "KP_Page_Down" | "KP_Next" => 0xff9b,
+        "KP_End" => 0xff9c,
+        "KP_Begin" => 0xff9d,
+        "KP_Insert" => 0xff9e,
+        "KP_Delete" => 0xff9f,
+        "KP_Multiply" => 0xffaa,
+        "KP_Add" => 0xffab,
+        "KP_Separator" => 0xffac,
+        "KP_Subtract" => 0xffad,
+        "KP_Decimal" => 0xffae,
+        "KP_Divide" => 0xffaf,
+        "KP_0" => 0xffb0,
+        "KP_1" => 0xffb1,
+        "KP_2" => 0xffb2,
+        "KP_3" => 0xffb3,
+        "KP_4" => 0xffb4,
+        "KP_5" => 0xffb5,
+        "KP_6" => 0xffb6,
+        "KP_7" => 0xffb7,
+        "KP_8" => 0xffb8,
+        "KP_9" => 0xffb9,
+        "KP_Equal" => 0xffbd,
+        "Shift_L" => 0xffe1,
+        "Shift_R" => 0xffe2,
+        "Control_L" => 0xffe3,
+        "Control_R" => 0xffe4,
+        "Caps_Lock" => 0xffe5,
+        "Shift_Lock" => 0xffe6,
+        "Meta_L" => 0xffe7,
+        "Meta_R" => 0xffe8,
+        "Alt_L" => 0xffe9,
+        "Alt_R" => 0xffea,
+        "Super_L" => 0xffeb,
+        "Super_R" => 0xffec,
+        "Hyper_L" => 0xffed,
+        "Hyper_R" => 0xffee,
+        "Delete" => 0xffff,
+        "XF86MonBrightnessUp" => 0x1008ff02,
+        "XF86MonBrightnessDown" => 0x1008ff03,
+        "XF86KbdBrightnessUp" => 0x1008ff05,
+        "XF86KbdBrightnessDown" => 0x1008ff06,
+        "XF86AudioLowerVolume" => 0x1008ff11,
+        "XF86AudioMute" => 0x1008ff12,
+        "XF86AudioRaiseVolume" => 0x1008ff13,
+        "XF86AudioPlay" => 0x1008ff14,
+        "XF86AudioStop" => 0x1008ff15,
+        "XF86AudioPrev" => 0x1008ff16,
+        "XF86AudioNext" => 0x1008ff17,
+        "XF86HomePage" => 0x1008ff18,
+        "XF86Mail" => 0x1008ff19,
+        "XF86Search" => 0x1008ff1b,
+        "XF86AudioRecord" => 0x1008ff1c,
+        "XF86Calculator" => 0x1008ff1d,
+        "XF86Back" => 0x1008ff26,
+        "XF86Forward" => 0x1008ff27,
+        "XF86Refresh" => 0x1008ff29,
+        "XF86PowerOff" => 0x1008ff2a,
+        "XF86Eject" => 0x1008ff2c,
+        "XF86ScreenSaver" => 0x1008ff2d,
+        "XF86Sleep" => 0x1008ff2f,
+        "XF86Favorites" => 0x1008ff30,
+        "XF86AudioPause" => 0x1008ff31,
+        "XF86AudioMedia" => 0x1008ff32,
+        "XF86Display" => 0x1008ff59,
+        "XF86Explorer" => 0x1008ff5d,
+        "XF86Tools" => 0x1008ff81,
+        "XF86WLAN" => 0x1008ff95,
+        "XF86TouchpadToggle" => 0x1008ffa9,
+        "XF86AudioMicMute" => 0x1008ffb2,
+        _ => return None,
+    };
+    Some(keysym)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_keys() {
+        assert_eq!(parse_key("Mod4+Shift+q"), Some((x::ModMask::N4 | x::ModMask::SHIFT, 0x71)));
+        assert_eq!(parse_key("Mod4+Q"), Some((x::ModMask::N4, 0x71)));
+        assert_eq!(parse_key("ctrl + Return"), Some((x::ModMask::CONTROL, 0xff0d)));
+        assert_eq!(parse_key("Mod4+F12"), Some((x::ModMask::N4, 0xffc9)));
+        assert_eq!(parse_key("KP_Enter"), Some((x::ModMask::empty(), 0xff8d)));
+        assert_eq!(parse_key("Mod1+KP_7"), Some((x::ModMask::N1, 0xffb7)));
+        assert_eq!(parse_key("Caps_Lock"), Some((x::ModMask::empty(), 0xffe5)));
+        assert_eq!(parse_key("Print"), Some((x::ModMask::empty(), 0xff61)));
+        assert_eq!(parse_key("Mod4+0x1008ff2f"), Some((x::ModMask::N4, 0x1008ff2f)));
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert_eq!(parse_key("Mod4+Enter"), None);
+        assert_eq!(parse_key("Hyper+q"), None);
+        assert_eq!(parse_key("F36"), None);
+        assert_eq!(parse_key("0xnope"), None);
+        assert_eq!(parse_key(""), None);
+    }
+}
@@ -1,6 +1,15 @@
-use log::info;
+use xcb::x;
 
 use crate::client::Client;
+use crate::tiling::{MasterArea, Rect, Tiling};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
 
 
 
@@ -8,53 +17,161 @@ pub struct Layout {
     width: u16,
     height: u16,
     pub clients: Vec<Client>,
+    tiling: Box<dyn Tiling>,
+    master: MasterArea,
 }
 
 impl Layout {
-    pub fn new(width: u16, height: u16) -> Self {
+    pub fn new(width: u16, height: u16, tiling: Box<dyn Tiling>, master: MasterArea) -> Self {
         Layout {
             width,
             height,
-            clients: Vec::new()
+            clients: Vec::new(),
+            tiling,
+            master,
         }
     }
 
-    pub fn position_new_client(&mut self, client: Client) {
+    pub fn set_size(&mut self, width: u16, height: u16, viewed_tags: u32) {
+        self.width = width;
+        self.height = height;
+
+        self.reorder_clients(viewed_tags);
+    }
+
+    pub fn change_master_factor(&mut self, delta: f32, viewed_tags: u32) {
+        self.master.factor = (self.master.factor + delta).clamp(0.1, 0.9);
+
+        self.reorder_clients(viewed_tags);
+    }
+
+    pub fn change_master_count(&mut self, delta: i32, viewed_tags: u32) {
+        self.master.count = self.master.count.saturating_add_signed(delta as isize);
+
+        self.reorder_clients(viewed_tags);
+    }
+
+    pub fn set_tiling(&mut self, tiling: Box<dyn Tiling>, viewed_tags: u32) {
+        self.tiling = tiling;
+
+        self.reorder_clients(viewed_tags);
+    }
+
+    pub fn get_tiling_name(&self) -> &'static str {
+        self.tiling.name()
+    }
+
+    pub fn stacks_clients(&self) -> bool {
+        self.tiling.stacks_clients()
+    }
+
+    pub fn get_master(&self) -> MasterArea {
+        self.master
+    }
+
+    pub fn set_master(&mut self, master: MasterArea, viewed_tags: u32) {
+        self.master = master;
+
+        self.reorder_clients(viewed_tags);
+    }
+
+    pub fn position_new_client(&mut self, client: Client, viewed_tags: u32) {
         self.clients.push(client);
 
-        self.reorder_clients();
+        self.reorder_clients(viewed_tags);
     }
 
-    pub fn remove_client(&mut self, client_index: usize) {
-        self.clients.remove(client_index);
+    pub fn remove_client(&mut self, client_index: usize, viewed_tags: u32) -> Client {
+        let client = self.clients.remove(client_index);
+
+        self.reorder_clients(viewed_tags);
 
-        self.reorder_clients();
+        client
     }
 
-    pub fn get_client_to_focus(&self) -> &Client {
-        self.clients.last().unwrap()
+    pub fn get_client_to_focus(&self, viewed_tags: u32) -> Option<&Client> {
+        self.clients.iter().rev().find(|client| client.is_visible(viewed_tags))
     }
-    
-    fn reorder_clients(&mut self) {
-        let client_count = self.clients.len();
 
-        if client_count > 1 {
-            self.clients.last_mut().unwrap().set_size(self.width / 2, self.height);
-            self.clients.last_mut().unwrap().set_pos(0, 0);
+    /// Returns the visible client after (or before) window in layout order, wrapping around at the ends.
+    pub fn get_adjacent_client(&self, window: x::Window, forward: bool, viewed_tags: u32) -> Option<x::Window> {
+        let order = self.get_layout_order(|client| client.is_visible(viewed_tags));
+        let position = order.iter().position(|i| self.clients[*i].window == window)?;
+        let next = if forward { (position + 1) % order.len() } else { (position + order.len() - 1) % order.len() };
+        Some(self.clients[order[next]].window).filter(|next| *next != window)
+    }
 
-            let client_stack_size = self.height / (client_count - 1) as u16;
+    /// Returns the nearest tiled client next to window in direction that shares part of its edge range.
+    pub fn get_client_in_direction(&self, window: x::Window, direction: Direction, viewed_tags: u32) -> Option<x::Window> {
+        let current = self.clients.iter().find(|client| client.window == window && client.is_tiled(viewed_tags))?;
+        let from = Self::get_client_rect(current);
+
+        self.clients.iter()
+            .filter(|client| client.window != window && client.is_tiled(viewed_tags))
+            .filter_map(|client| {
+                let to = Self::get_client_rect(client);
+                // distance to the candidate in direction and both extents across it
+                let (distance, (from_start, from_length), (to_start, to_length)) = match direction {
+                    Direction::Left => (from.x as i32 - (to.x as i32 + to.width as i32), (from.y, from.height), (to.y, to.height)),
+                    Direction::Right => (to.x as i32 - (from.x as i32 + from.width as i32), (from.y, from.height), (to.y, to.height)),
+                    Direction::Up => (from.y as i32 - (to.y as i32 + to.height as i32), (from.x, from.width), (to.x, to.width)),
+                    Direction::Down => (to.y as i32 - (from.y as i32 + from.height as i32), (from.x, from.width), (to.x, to.width)),
+                };
+                let overlap = (from_start as i32 + from_length as i32).min(to_start as i32 + to_length as i32)
+                    - (from_start as i32).max(to_start as i32);
+                // prefer the candidate whose center is closest across the direction
+                let offset = ((from_start as i32 * 2 + from_length as i32) - (to_start as i32 * 2 + to_length as i32)).abs();
+                (distance >= 0 && overlap > 0).then_some((distance, offset, client.window))
+            })
+            .min_by_key(|(distance, offset, _)| (*distance, *offset))
+            .map(|(_, _, window)| window)
+    }
 
-            for i in 0..(self.clients.len() - 1) {
-                self.clients[i].set_pos((self.width / 2) as i16, (i as u16 * client_stack_size) as i16);
-                self.clients[i].set_size(self.width / 2, client_stack_size);
-            }
+    /// Swaps window with the master client, or the master with the next tiled client if window already is the master.
+    pub fn swap_with_master(&mut self, window: x::Window, viewed_tags: u32) {
+        let order = self.get_layout_order(|client| client.is_tiled(viewed_tags));
+        let Some(position) = order.iter().position(|i| self.clients[*i].window == window) else {
+            return;
+        };
+        let other = if position == 0 { 1 } else { 0 };
+        if other < order.len() {
+            self.clients.swap(order[position], order[other]);
+            self.reorder_clients(viewed_tags);
         }
+    }
 
-        else if client_count == 1 {
-            self.clients.last_mut().unwrap().set_size(self.width, self.height);
-            self.clients.last_mut().unwrap().set_pos(0, 0);
+    /// Moves window one place up (towards the master area) or down the stack, wrapping around at the ends.
+    pub fn move_client(&mut self, window: x::Window, up: bool, viewed_tags: u32) {
+        let order = self.get_layout_order(|client| client.is_tiled(viewed_tags));
+        let Some(position) = order.iter().position(|i| self.clients[*i].window == window) else {
+            return;
+        };
+        let other = if up { (position + order.len() - 1) % order.len() } else { (position + 1) % order.len() };
+        if other != position {
+            self.clients.swap(order[position], order[other]);
+            self.reorder_clients(viewed_tags);
         }
     }
-}
 
+    // indices into clients, newest first like they are arranged
+    fn get_layout_order(&self, filter: impl Fn(&Client) -> bool) -> Vec<usize> {
+        (0..self.clients.len()).rev().filter(|i| filter(&self.clients[*i])).collect()
+    }
+
+    fn get_client_rect(client: &Client) -> Rect {
+        Rect::new(client.posX, client.posY, client.width, client.height)
+    }
+
+    /// Arranges only the tiled clients on one of the viewed tags, all others keep their geometry.
+    /// Clients are laid out newest first, so the most recent ones fill the master area.
+    pub fn reorder_clients(&mut self, viewed_tags: u32) {
+        let visible = self.get_layout_order(|client| client.is_tiled(viewed_tags));
 
+        let rects = self.tiling.arrange(Rect::new(0, 0, self.width, self.height), visible.len(), self.master);
+
+        for (i, rect) in visible.into_iter().zip(rects) {
+            self.clients[i].set_pos(rect.x, rect.y);
+            self.clients[i].set_size(rect.width, rect.height);
+        }
+    }
+}
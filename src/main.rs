@@ -2,59 +2,32 @@ pub mod xmanager;
 pub mod client;
 pub mod monitor;
 pub mod layout;
+pub mod tiling;
+pub mod keybindings;
+pub mod state;
 mod config;
 
+use std::collections::HashMap;
+use std::env;
+use std::os::unix::process::CommandExt;
 use std::process::{Command, self};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::Instant;
 
-use log::{debug, error, info, trace, warn};
-use log4rs;
+use log::{error, info, warn};
 
-use xcb::x::CURRENT_TIME;
-use xcb::{x, randr, Connection};
-use xcb::{Xid};
+use xcb::{x, randr, xkb, BaseEvent};
 
 use crate::client::Client;
-use crate::config::Settings;
+use crate::config::{Actions, FocusModel, FocusStealing, Settings};
+use crate::keybindings::KeyBindings;
+use crate::layout::Direction;
 use crate::monitor::Monitor;
-use crate::xmanager::Xmanager;
+use crate::state::SavedState;
+use crate::tiling::Rect;
+use crate::xmanager::{ClientRequest, Xmanager};
 
 pub trait WindowConfiguration {
-    fn set_window_configuration(&self, window: x::Window, x: i32, y: i32, width: u32, height: u32);
-}
-
-struct WmAtoms {
-    wm_protocols: xcb::x::Atom,
-    wm_delete_window: xcb::x::Atom,
-    wm_state: xcb::x::Atom,
-    wm_take_focus: xcb::x::Atom,
-}
-struct NetAtoms {
-    net_active_window: xcb::x::Atom,
-    net_supported: xcb::x::Atom,
-    net_wm_name: xcb::x::Atom,
-    net_wm_state: xcb::x::Atom,
-    net_supporting_wm_check: xcb::x::Atom,
-    net_wm_state_fullscreen: xcb::x::Atom,
-    net_wm_state_window_type: xcb::x::Atom,
-    net_wm_window_type_dialog: xcb::x::Atom,
-    net_client_list: xcb::x::Atom,
-}
-
-impl NetAtoms {
-    fn as_array(&self) -> [x::Atom; 9] {
-        [
-            self.net_active_window,
-            self.net_supported,
-            self.net_wm_name,
-            self.net_wm_state,
-            self.net_supporting_wm_check,
-            self.net_wm_state_fullscreen,
-            self.net_wm_state_window_type,
-            self.net_wm_window_type_dialog,
-            self.net_client_list,
-        ]
-    }
+    fn set_window_configuration(&self, window: x::Window, x: i32, y: i32, width: u32, height: u32, border_width: u32);
 }
 
 // Many xcb functions return a `xcb::Result` or compatible result.
@@ -69,50 +42,443 @@ fn main() -> xcb::Result<()> {
     println!("{:?}", settings);
     
     
-    let xmanager = Xmanager::init();
-    let mut monitors = Monitor::creat_monitors(&xmanager);
+    let mut xmanager = Xmanager::init();
+    let mut monitors = Monitor::creat_monitors(&xmanager, &settings);
+    xmanager.set_desktops(&settings.workspaces);
+    xmanager.set_current_desktop(0);
 
-    Command::new("alacritty").spawn();
-    Command::new("/home/jonas/.config/dswm/autorun.sh").spawn().unwrap_or_else(|err| {
-        info!("autorun err: {}", err);
-        process::exit(1);
-    });
+    let mut keybindings = KeyBindings::new(&settings.keymap, &settings.mouse_modifier);
+    keybindings.grab_keys(&xmanager);
+    keybindings.grab_buttons(&xmanager);
 
+    // after a restart the session is already running
+    match xmanager.take_saved_state() {
+        Some(state) => Monitor::restore_state(&xmanager, &mut monitors, &SavedState::parse(&state)),
+        None => {
+            Command::new("alacritty").spawn().ok();
+            if let Err(err) = Command::new("/home/jonas/.config/dswm/autorun.sh").spawn() {
+                info!("autorun err: {}", err);
+                process::exit(1);
+            }
+        }
+    }
+
+
+    let mut focused_monitor = 0;
+    // monitor the pointer was last seen on, so motion only switches monitors when crossing to another one
+    let mut pointer_monitor = 0;
+    // managed windows and the index of their monitor, rebuilt with the client lists
+    let mut managed = HashMap::new();
+    // managed windows in the order they were mapped, for _NET_CLIENT_LIST
+    let mut mapping_order = Vec::new();
+    update_client_lists(&xmanager, &monitors, &mut managed, &mut mapping_order);
+
+    // windows left from a previous window manager or session go to the monitor showing most of them
+    for window in xmanager.get_existing_windows() {
+        let Some(geometry) = xmanager.get_window_geometry(window) else {
+            continue;
+        };
+        // windows outside of every monitor go to the focused one, ties go to the first monitor
+        let target = (0..monitors.len()).rev()
+            .max_by_key(|i| monitors[*i].get_overlap(geometry))
+            .filter(|i| monitors[*i].get_overlap(geometry) > 0)
+            .unwrap_or(focused_monitor);
+        manage_window(&xmanager, &mut monitors, &managed, &mut focused_monitor, window, geometry, target);
+        update_client_lists(&xmanager, &monitors, &mut managed, &mut mapping_order);
+    }
+
+    // windows asked to close and when their client gets killed if they are still around
+    let mut pending_kills: Vec<(x::Window, Instant)> = Vec::new();
 
     loop {
-        match xmanager.conn.wait_for_event()? {
+        kill_unresponsive_clients(&xmanager, &managed, &mut pending_kills);
+        let deadline = pending_kills.iter().map(|(_, deadline)| *deadline).min();
+        let event = match xmanager.next_event(deadline) {
+            Ok(Some(event)) => event,
+            Ok(None) => continue,
+            // errors of unchecked requests, usually for windows that are already gone
+            Err(xcb::Error::Protocol(err)) => {
+                warn!("X error: {err}");
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        match event {
             xcb::Event::X(x::Event::ClientMessage(ev)) => {
-                info!("message");
-                // We have received a message from the server
-                if let x::ClientMessageData::Data32([atom, ..]) = ev.data() {
-                    info!("{:?}", atom);
+                info!("message {:?}", ev);
+                match xmanager.parse_client_message(&ev) {
+                    Some(ClientRequest::Fullscreen { window, action }) => {
+                        if let Some(i) = find_monitor(&managed, window) {
+                            monitors[i].set_fullscreen(&xmanager, window, action);
+                        }
+                    }
+                    Some(ClientRequest::CurrentDesktop { desktop }) => {
+                        if let Some(tags) = monitors[focused_monitor].get_tags_from_desktop(desktop) {
+                            monitors[focused_monitor].view_tags(&xmanager, tags);
+                        }
+                    }
+                    Some(ClientRequest::WindowDesktop { window, desktop }) => {
+                        if let Some(i) = find_monitor(&managed, window) {
+                            if let Some(tags) = monitors[i].get_tags_from_desktop(desktop) {
+                                monitors[i].set_client_tags(&xmanager, window, tags);
+                            }
+                        }
+                    }
+                    Some(ClientRequest::ActiveWindow { window, from_pager }) => {
+                        // pagers act on behalf of the user and are always honored
+                        match settings.focus_stealing {
+                            FocusStealing::deny if !from_pager => {}
+                            policy => {
+                                let allow_switch = from_pager || policy == FocusStealing::allow;
+                                if let Some(i) = find_monitor(&managed, window) {
+                                    if monitors[i].activate_client(&xmanager, window, allow_switch) {
+                                        focus_monitor(&xmanager, &mut monitors, &mut focused_monitor, i);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    Some(ClientRequest::CloseWindow { window }) => {
+                        close_window(&xmanager, &settings, &mut pending_kills, window);
+                    }
+                    None => {}
                 }
+                update_client_lists(&xmanager, &monitors, &mut managed, &mut mapping_order);
+                xmanager.discard_enter_events();
             }
             xcb::Event::X(x::Event::MapRequest(ev)) => {
                 info!("MapRequest {:?}", ev);
-                monitors[0].map_client(&xmanager, Client::new(ev.window(), 0, 0, 0, 0));
+                let geometry = xmanager.get_window_geometry(ev.window()).unwrap_or(Rect::new(0, 0, 0, 0));
+                let target = focused_monitor;
+                manage_window(&xmanager, &mut monitors, &managed, &mut focused_monitor, ev.window(), geometry, target);
+                update_client_lists(&xmanager, &monitors, &mut managed, &mut mapping_order);
+                xmanager.discard_enter_events();
 
             }
             xcb::Event::X(x::Event::UnmapNotify(ev)) => {
                 info!("UnmapNotify {:?}", ev);
-                monitors[0].unmap_window(&xmanager, ev.window());
+                // managed windows report their unmap to the root and to themselves, only handle it once.
+                // Clients withdrawing a window dswm has hidden send a synthetic one to the root instead.
+                let synthetic = ev.is_from_send_event() && ev.event() == xmanager.screen.root();
+                if let Some(i) = find_monitor(&managed, ev.window()).filter(|_| ev.event() == ev.window() || synthetic) {
+                    monitors[i].unmap_window(&xmanager, ev.window(), synthetic);
+                    update_client_lists(&xmanager, &monitors, &mut managed, &mut mapping_order);
+                    xmanager.discard_enter_events();
+                }
 
             }
+            xcb::Event::X(x::Event::ConfigureRequest(ev)) => {
+                info!("ConfigureRequest {:?}", ev);
+                match find_monitor(&managed, ev.window()) {
+                    Some(i) => monitors[i].configure_request(&xmanager, &ev),
+                    None => xmanager.configure_unmanaged(&ev),
+                }
+            }
+            xcb::Event::X(x::Event::DestroyNotify(ev)) => {
+                info!("DestroyNotify {:?}", ev);
+                if let Some(i) = find_monitor(&managed, ev.window()) {
+                    monitors[i].remove_window(&xmanager, ev.window());
+                    update_client_lists(&xmanager, &monitors, &mut managed, &mut mapping_order);
+                    xmanager.discard_enter_events();
+                }
+            }
+            xcb::Event::X(x::Event::PropertyNotify(ev)) if ev.atom() == x::ATOM_WM_NORMAL_HINTS => {
+                if let Some(i) = find_monitor(&managed, ev.window()) {
+                    monitors[i].update_size_hints(&xmanager, ev.window());
+                }
+            }
             xcb::Event::X(x::Event::EnterNotify(ev)) => {
-                xmanager.focus_window(ev.event());
                 info!("enter: {:?}", ev);
+                // crossings from grabs or between a client and its subwindows are no pointer movement into the client
+                if settings.focus_model == FocusModel::sloppy
+                    && ev.mode() == x::NotifyMode::Normal
+                    && ev.detail() != x::NotifyDetail::Inferior {
+                    let target = find_monitor(&managed, ev.event()).or(find_monitor_at(&monitors, ev.root_x(), ev.root_y()));
+                    if let Some(i) = target {
+                        pointer_monitor = i;
+                        focus_monitor(&xmanager, &mut monitors, &mut focused_monitor, i);
+                        monitors[i].focus_client(&xmanager, ev.event());
+                        update_client_lists(&xmanager, &monitors, &mut managed, &mut mapping_order);
+                    }
+                }
             }
             xcb::Event::X(x::Event::KeyPress(ev)) => {
                 info!("key: {:?}", ev);
+                if let Some(binding) = keybindings.get_binding(ev.detail(), ev.state()) {
+                    if binding.action == Actions::restart {
+                        restart(xmanager, &monitors);
+                    }
+                    handle_action(&xmanager, &settings, &mut monitors, &mut focused_monitor, &mut pending_kills, binding.action, &binding.args);
+                    update_client_lists(&xmanager, &monitors, &mut managed, &mut mapping_order);
+                    xmanager.discard_enter_events();
+                }
+            }
+            // clicks on unfocused clients caught by their focus button grab
+            xcb::Event::X(x::Event::ButtonPress(ev)) if ev.event() != xmanager.screen.root() => {
+                if let Some(i) = find_monitor(&managed, ev.event()) {
+                    focus_monitor(&xmanager, &mut monitors, &mut focused_monitor, i);
+                    monitors[i].focus_client(&xmanager, ev.event());
+                }
+                xmanager.replay_pointer(ev.time());
+                update_client_lists(&xmanager, &monitors, &mut managed, &mut mapping_order);
+            }
+            xcb::Event::X(x::Event::ButtonPress(ev)) => {
+                info!("button: {:?}", ev);
+                let action = keybindings.get_mouse_action(ev.detail(), ev.state());
+                if let (Some(action), Some(i)) = (action, find_monitor(&managed, ev.child())) {
+                    focus_monitor(&xmanager, &mut monitors, &mut focused_monitor, i);
+                    monitors[i].start_drag(&xmanager, ev.child(), action, ev.root_x(), ev.root_y());
+                    update_client_lists(&xmanager, &monitors, &mut managed, &mut mapping_order);
+                }
+            }
+            xcb::Event::X(x::Event::MotionNotify(ev)) if monitors.iter().any(|monitor| monitor.is_dragging()) => {
+                for monitor in monitors.iter_mut() {
+                    monitor.drag_motion(&xmanager, ev.root_x(), ev.root_y(), ev.time());
+                }
+            }
+            // the pointer moving over the root onto another monitor, e.g. one without clients
+            xcb::Event::X(x::Event::MotionNotify(ev)) if settings.focus_model == FocusModel::sloppy => {
+                if let Some(i) = find_monitor_at(&monitors, ev.root_x(), ev.root_y()).filter(|i| *i != pointer_monitor) {
+                    pointer_monitor = i;
+                    focus_monitor(&xmanager, &mut monitors, &mut focused_monitor, i);
+                }
+            }
+            xcb::Event::X(x::Event::ButtonRelease(_)) => {
+                for monitor in monitors.iter_mut() {
+                    monitor.stop_drag();
+                }
+            }
+            xcb::Event::Xkb(xkb::Event::MapNotify(_)) | xcb::Event::Xkb(xkb::Event::NewKeyboardNotify(_)) => {
+                info!("keyboard mapping changed, regrabbing keys");
+                xmanager.update_keysym_table();
+                keybindings.grab_keys(&xmanager);
+                keybindings.grab_buttons(&xmanager);
+            }
+            xcb::Event::RandR(xcb::randr::Event::Notify(ev)) => {
+                if let randr::NotifyData::Cc(cc) = ev.u() {
+                    Monitor::handle_crtc_change(&xmanager, &settings, &mut monitors, &mut focused_monitor, cc);
+                    pointer_monitor = pointer_monitor.min(monitors.len() - 1);
+                    update_client_lists(&xmanager, &monitors, &mut managed, &mut mapping_order);
+                    xmanager.discard_enter_events();
+                }
+            }
+            _ev => { //info!("other {:?}", ev); 
+            }
+        }
+    }
+}
+
+/// Leaves the window state on the root for the new process and replaces this one with it.
+fn restart(xmanager: Xmanager, monitors: &[Monitor]) -> ! {
+    info!("restarting dswm");
+    // argv[0] instead of /proc/self/exe, so a rebuilt binary is picked up
+    let Some(exe) = env::args_os().next() else {
+        error!("unable to find the dswm binary");
+        process::exit(1);
+    };
+    xmanager.set_saved_state(&Monitor::save_state(monitors).serialize());
+    // the new process can only redirect the root once this connection is closed
+    drop(xmanager);
+
+    let err = Command::new(exe).args(env::args_os().skip(1)).exec();
+    error!("failed to restart: {}", err);
+    process::exit(1);
+}
+
+fn update_client_lists(xmanager: &Xmanager, monitors: &[Monitor], managed: &mut HashMap<x::Window, usize>, mapping_order: &mut Vec<x::Window>) {
+    managed.clear();
+    for (i, monitor) in monitors.iter().enumerate() {
+        managed.extend(monitor.get_windows().map(|window| (window, i)));
+    }
+
+    // the layout order changes when clients are moved around, so new windows are appended to the mapping order instead
+    mapping_order.retain(|window| managed.contains_key(window));
+    for window in monitors.iter().flat_map(|monitor| monitor.get_windows()) {
+        if !mapping_order.contains(&window) {
+            mapping_order.push(window);
+        }
+    }
+
+    let stacking: Vec<x::Window> = monitors.iter().flat_map(|monitor| monitor.get_windows_stacking()).collect();
+    xmanager.set_client_lists(mapping_order, &stacking);
+}
+
+fn handle_action(xmanager: &Xmanager, settings: &Settings, monitors: &mut [Monitor], focused_monitor: &mut usize, pending_kills: &mut Vec<(x::Window, Instant)>, action: Actions, args: &Option<Vec<String>>) {
+    info!("action: {:?} {:?}", action, args);
+    let current = *focused_monitor;
+    match action {
+        Actions::quit => {
+            info!("quitting dswm");
+            process::exit(0);
+        }
+        // needs to own the connection to close it, handled in the event loop
+        Actions::restart => {}
+        Actions::close => {
+            if let Some(window) = monitors[current].get_focused() {
+                close_window(xmanager, settings, pending_kills, window);
             }
-            xcb::Event::RandR(xcb::randr::Event::Notify(ev)) => match ev.u() {
-                randr::NotifyData::Cc(cc) => {
-                    Monitor::handle_crtc_change(&mut monitors, cc);
+        }
+        Actions::focus_monitor => {
+            if let Some(index) = get_monitor_arg(monitors.len(), current, args) {
+                focus_monitor(xmanager, monitors, focused_monitor, index);
+                monitors[index].warp_to_focused(xmanager);
+            }
+        }
+        Actions::send_to_monitor => {
+            let Some(window) = monitors[current].get_focused() else {
+                return;
+            };
+            if let Some(index) = get_monitor_arg(monitors.len(), current, args).filter(|index| *index != current) {
+                if let Some(client) = monitors[current].take_client(xmanager, window) {
+                    monitors[index].add_client(xmanager, client);
                 }
-                _ => {}
-            },
-            ev => { //info!("other {:?}", ev); 
             }
         }
+        Actions::focus => {
+            let arg = args.as_ref().and_then(|args| args.first()).map(String::as_str);
+            match arg {
+                Some("next") => monitors[current].focus_adjacent(xmanager, true),
+                Some("prev") => monitors[current].focus_adjacent(xmanager, false),
+                Some("left") => monitors[current].focus_direction(xmanager, Direction::Left),
+                Some("right") => monitors[current].focus_direction(xmanager, Direction::Right),
+                Some("up") => monitors[current].focus_direction(xmanager, Direction::Up),
+                Some("down") => monitors[current].focus_direction(xmanager, Direction::Down),
+                Some("swap_master") => monitors[current].swap_focused_with_master(xmanager),
+                Some("move_up") => monitors[current].move_focused(xmanager, true),
+                Some("move_down") => monitors[current].move_focused(xmanager, false),
+                _ => error!("unknown focus argument {:?}", arg),
+            }
+        }
+        Actions::view_workspace => {
+            if let Some(tags) = get_workspace_tags(&monitors[current], args) {
+                monitors[current].view_tags(xmanager, tags);
+            }
+        }
+        Actions::toggle_view_workspace => {
+            if let Some(tags) = get_workspace_tags(&monitors[current], args) {
+                monitors[current].toggle_view_tags(xmanager, tags);
+            }
+        }
+        Actions::move_to_workspace => {
+            if let Some(tags) = get_workspace_tags(&monitors[current], args) {
+                monitors[current].set_focused_tags(xmanager, tags);
+            }
+        }
+        Actions::toggle_client_workspace => {
+            if let Some(tags) = get_workspace_tags(&monitors[current], args) {
+                monitors[current].toggle_focused_tags(xmanager, tags);
+            }
+        }
+        Actions::set_layout => {
+            let name = args.as_ref().and_then(|args| args.first());
+            match name.and_then(|name| tiling::tiling_from_name(name)) {
+                Some(tiling) => monitors[current].set_tiling(xmanager, tiling),
+                None => error!("unknown layout {:?}", name),
+            }
+        }
+        Actions::change_master_factor => {
+            if let Some(delta) = get_numeric_arg(args) {
+                monitors[current].change_master_factor(xmanager, delta);
+            }
+        }
+        Actions::change_master_count => {
+            if let Some(delta) = get_numeric_arg(args) {
+                monitors[current].change_master_count(xmanager, delta);
+            }
+        }
+        Actions::toggle_floating => {
+            monitors[current].toggle_floating(xmanager);
+        }
+    }
+}
+
+fn get_numeric_arg<T: std::str::FromStr>(args: &Option<Vec<String>>) -> Option<T> {
+    let arg = args.as_ref().and_then(|args| args.first());
+    let value = arg.and_then(|arg| arg.parse().ok());
+    if value.is_none() {
+        error!("expected a numeric argument, got {:?}", arg);
+    }
+    value
+}
+
+/// Manages a new window on monitor, transients open on the monitor of their parent instead.
+fn manage_window(xmanager: &Xmanager, monitors: &mut [Monitor], managed: &HashMap<x::Window, usize>, focused_monitor: &mut usize, window: x::Window, geometry: Rect, monitor: usize) {
+    if managed.contains_key(&window) {
+        return;
+    }
+    let target = xmanager.get_transient_for(window)
+        .and_then(|parent| find_monitor(managed, parent))
+        .unwrap_or(monitor);
+    focus_monitor(xmanager, monitors, focused_monitor, target);
+    monitors[target].map_client(xmanager, Client::new(window, geometry.x, geometry.y, geometry.height, geometry.width));
+}
+
+fn close_window(xmanager: &Xmanager, settings: &Settings, pending_kills: &mut Vec<(x::Window, Instant)>, window: x::Window) {
+    if xmanager.close_window(window) {
+        if let Some(timeout) = settings.get_close_timeout() {
+            pending_kills.push((window, Instant::now() + timeout));
+        }
+    }
+}
+
+/// Kills the clients whose windows are still managed once their close timeout ran out.
+fn kill_unresponsive_clients(xmanager: &Xmanager, managed: &HashMap<x::Window, usize>, pending_kills: &mut Vec<(x::Window, Instant)>) {
+    let now = Instant::now();
+    pending_kills.retain(|&(window, deadline)| {
+        if deadline > now {
+            return true;
+        }
+        // windows on hidden workspaces count too, they may have been closed from a pager
+        if managed.contains_key(&window) {
+            info!("window {:?} did not close in time, killing its client", window);
+            xmanager.kill_client(window);
+        }
+        false
+    });
+}
+
+fn find_monitor(managed: &HashMap<x::Window, usize>, window: x::Window) -> Option<usize> {
+    managed.get(&window).copied()
+}
+
+fn find_monitor_at(monitors: &[Monitor], x: i16, y: i16) -> Option<usize> {
+    monitors.iter().position(|monitor| monitor.contains_point(x, y))
+}
+
+/// Moves the input focus to the monitor at index, which then focuses its own focused client.
+fn focus_monitor(xmanager: &Xmanager, monitors: &mut [Monitor], focused_monitor: &mut usize, index: usize) {
+    if index == *focused_monitor {
+        return;
+    }
+    monitors[*focused_monitor].set_active(xmanager, false);
+    monitors[index].set_active(xmanager, true);
+    *focused_monitor = index;
+}
+
+// "next" (the default) or "prev", wrapping around the monitors in randr order
+fn get_monitor_arg(count: usize, current: usize, args: &Option<Vec<String>>) -> Option<usize> {
+    let arg = args.as_ref().and_then(|args| args.first()).map(String::as_str);
+    match arg {
+        None | Some("next") => Some((current + 1) % count),
+        Some("prev") => Some((current + count - 1) % count),
+        _ => {
+            error!("unknown monitor argument {:?}", arg);
+            None
+        }
+    }
+}
+
+/// Combines all workspace names in args into one tag mask, so several workspaces can be viewed at once.
+fn get_workspace_tags(monitor: &Monitor, args: &Option<Vec<String>>) -> Option<u32> {
+    let mut tags = 0;
+    for name in args.iter().flatten() {
+        match monitor.get_workspace_tag(name) {
+            Some(tag) => tags |= tag,
+            None => error!("unknown workspace {:?}", name),
+        }
+    }
+    if tags == 0 {
+        return None;
     }
+    Some(tags)
 }
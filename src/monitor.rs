@@ -1,10 +1,28 @@
-use log::{debug, error, info, trace, warn};
-use xcb::{randr, x};
+use log::{error, info};
+use xcb::{randr, x, Xid};
 use xcb::x::CURRENT_TIME;
 
-use crate::{layout::Layout, xmanager::Xmanager, client::Client, WindowConfiguration};
+use crate::{layout::{Direction, Layout}, xmanager::{StateAction, WmState, Xmanager}, client::Client, WindowConfiguration};
+use crate::config::{Appearance, FocusModel, Settings};
+use crate::keybindings::MouseAction;
+use crate::state::{SavedClient, SavedMonitor, SavedState};
+use crate::tiling::{self, MasterArea, Rect, Tiling};
 
 
+// _NET_WM_DESKTOP value for clients shown on all desktops
+const ALL_DESKTOPS: u32 = 0xFFFFFFFF;
+
+// state of a window being moved or resized with the mouse
+struct Drag {
+    window: x::Window,
+    action: MouseAction,
+    pointer_x: i16,
+    pointer_y: i16,
+    start_rect: Rect,
+    last_motion: x::Timestamp,
+}
+
+#[allow(non_snake_case)]
 pub struct Monitor {
     crtc: randr::Crtc,
     posX: i16,
@@ -12,17 +30,26 @@ pub struct Monitor {
     height: u16,
     width: u16,
     layout: Layout,
+    workspace_names: Vec<String>,
+    viewed_tags: u32,
+    focused: Option<x::Window>,
+    // the monitor holding the input focus, others only remember their focused client
+    active: bool,
+    appearance: Appearance,
+    focus_model: FocusModel,
+    warp_pointer: bool,
+    drag: Option<Drag>,
 }
 
 impl Monitor {
-    pub fn creat_monitors(xmanager: &Xmanager) -> Vec<Monitor> {
+    pub fn creat_monitors(xmanager: &Xmanager, settings: &Settings) -> Vec<Monitor> {
         //let cookie = xmanager.conn.send_request(&xinerama::QueryScreens {});
         let cookie = xmanager.conn.send_request(&randr::GetScreenResources {
             window: xmanager.screen.root(),
         });
         let reply = xmanager.conn.wait_for_reply(cookie).unwrap();
 
-        let mut monitors = Vec::new();
+        let mut monitors: Vec<Monitor> = Vec::new();
 
         for crtc in reply.crtcs() {
 
@@ -32,64 +59,997 @@ impl Monitor {
             });
             let reply = xmanager.conn.wait_for_reply(cookie).unwrap();
             info!("sceen geo: {}, {}, {}, {}", reply.x(), reply.y(), reply.width(), reply.height());
-            monitors.push(Monitor {
-                crtc: *crtc,
-                posX: reply.x(),
-                posY: reply.y(),
-                width: reply.width(),
-                height: reply.height(),
-                layout: Layout::new(reply.width(), reply.height()),
-            });
+            let rect = Rect::new(reply.x(), reply.y(), reply.width(), reply.height());
+            // disabled crtcs have no size and mirrored outputs share one monitor
+            if rect.width == 0 || rect.height == 0 || monitors.iter().any(|monitor| monitor.get_rect() == rect) {
+                continue;
+            }
+            let active = monitors.is_empty();
+            monitors.push(Self::new(*crtc, rect, settings, active));
+        }
+
+        if monitors.is_empty() {
+            error!("no active crtc found, using the whole screen");
+            let screen = &xmanager.screen;
+            let rect = Rect::new(0, 0, screen.width_in_pixels(), screen.height_in_pixels());
+            monitors.push(Self::new(randr::Crtc::none(), rect, settings, true));
         }
 
         monitors
     }
 
-    pub fn handle_crtc_change(monitors: &mut Vec<Monitor>, crtc_change: randr::CrtcChange) {
+    fn new(crtc: randr::Crtc, rect: Rect, settings: &Settings, active: bool) -> Self {
+        Monitor {
+            crtc,
+            posX: rect.x,
+            posY: rect.y,
+            width: rect.width,
+            height: rect.height,
+            layout: Layout::new(rect.width, rect.height, Self::default_tiling(settings), MasterArea {
+                factor: settings.master_factor,
+                count: settings.master_count,
+            }),
+            workspace_names: settings.workspaces.clone(),
+            viewed_tags: 1,
+            focused: None,
+            active,
+            appearance: settings.appearance.clone(),
+            focus_model: settings.focus_model,
+            warp_pointer: settings.warp_pointer,
+            drag: None,
+        }
+    }
+
+    fn default_tiling(settings: &Settings) -> Box<dyn Tiling> {
+        tiling::tiling_from_name(&settings.layout).unwrap_or_else(|| {
+            error!("config error: unknown layout \"{}\"", settings.layout);
+            tiling::tiling_from_name("master_stack_left").unwrap()
+        })
+    }
+
+    /// Follows an output being enabled, disabled or changing its mode. Clients of a removed monitor
+    /// move to the focused one, or the first one if the focused monitor was removed.
+    pub fn handle_crtc_change(xmanager: &Xmanager, settings: &Settings, monitors: &mut Vec<Monitor>, focused_monitor: &mut usize, crtc_change: randr::CrtcChange) {
+        let rect = Rect::new(crtc_change.x(), crtc_change.y(), crtc_change.width(), crtc_change.height());
+        let enabled = rect.width > 0 && rect.height > 0;
+        info!("crtc {:?} changed to {:?}", crtc_change.crtc(), rect);
+
+        let index = monitors.iter().position(|monitor| monitor.crtc == crtc_change.crtc());
+        match index {
+            Some(i) if enabled => monitors[i].set_geometry(xmanager, rect),
+            // the last monitor stays, clients need somewhere to live until an output comes back
+            Some(i) if monitors.len() > 1 => {
+                let mut removed = monitors.remove(i);
+                let target = if *focused_monitor == i { 0 } else if *focused_monitor > i { *focused_monitor - 1 } else { *focused_monitor };
+                *focused_monitor = target;
+                if removed.active {
+                    monitors[target].set_active(xmanager, true);
+                }
+                let clients = removed.layout.clients.drain(..).collect();
+                monitors[target].receive_clients(xmanager, clients, removed.viewed_tags);
+            }
+            Some(_) => {}
+            None if !enabled => {}
+            // a monitor without crtc covers the screen because no output was active at startup
+            None if monitors.len() == 1 && monitors[0].crtc.is_none() => {
+                monitors[0].crtc = crtc_change.crtc();
+                monitors[0].set_geometry(xmanager, rect);
+            }
+            None if monitors.iter().any(|monitor| monitor.get_rect() == rect) => {}
+            None => monitors.push(Self::new(crtc_change.crtc(), rect, settings, false)),
+        }
+    }
+
+    pub fn save_state(monitors: &[Monitor]) -> SavedState {
+        let mut state = SavedState::default();
         for monitor in monitors {
-            if monitor.crtc == crtc_change.crtc() {
-                monitor.posX = crtc_change.x();
-                monitor.posY = crtc_change.y();
-                monitor.height = crtc_change.height();
-                monitor.width = crtc_change.width();
+            let crtc = monitor.crtc.resource_id();
+            let master = monitor.layout.get_master();
+            state.monitors.push(SavedMonitor {
+                crtc,
+                viewed_tags: monitor.viewed_tags,
+                tiling: monitor.layout.get_tiling_name().to_owned(),
+                master_factor: master.factor,
+                master_count: master.count,
+                focused: monitor.focused.map_or(0, |window| window.resource_id()),
+            });
+            for client in &monitor.layout.clients {
+                state.clients.push(SavedClient {
+                    crtc,
+                    window: client.window.resource_id(),
+                    tags: client.tags,
+                    floating: client.floating,
+                    fullscreen: client.fullscreen,
+                    float_rect: client.float_rect,
+                });
+            }
+        }
+        state
+    }
+
+    /// Puts windows back where a previous dswm process had them, monitors are matched by crtc.
+    /// Clients of monitors that are gone end up on the first one.
+    pub fn restore_state(xmanager: &Xmanager, monitors: &mut [Monitor], state: &SavedState) {
+        let find = |monitors: &[Monitor], crtc: u32| monitors.iter().position(|monitor| monitor.crtc.resource_id() == crtc);
+
+        for saved in &state.monitors {
+            let Some(i) = find(monitors, saved.crtc) else {
+                continue;
+            };
+            let monitor = &mut monitors[i];
+            if let Some(tiling) = tiling::tiling_from_name(&saved.tiling) {
+                monitor.layout.set_tiling(tiling, monitor.viewed_tags);
+            }
+            // the property can be written by anyone, keep the factor in the range the config allows
+            let factor = if saved.master_factor.is_finite() { saved.master_factor.clamp(0.1, 0.9) } else { 0.5 };
+            monitor.layout.set_master(MasterArea { factor, count: saved.master_count }, monitor.viewed_tags);
+            let tags = saved.viewed_tags & monitor.get_all_tags();
+            if tags != 0 {
+                monitor.viewed_tags = tags;
+            }
+            if monitor.active {
+                xmanager.set_current_desktop(monitor.get_current_desktop());
+            }
+        }
+
+        for saved in &state.clients {
+            let i = find(monitors, saved.crtc).unwrap_or(0);
+            monitors[i].restore_client(xmanager, saved);
+        }
+
+        for saved in &state.monitors {
+            if let Some(i) = find(monitors, saved.crtc) {
+                let focused = monitors[i].layout.clients.iter().find(|client| client.window.resource_id() == saved.focused).map(|client| client.window);
+                monitors[i].set_focus(xmanager, focused);
+            }
+        }
+    }
+
+    fn restore_client(&mut self, xmanager: &Xmanager, saved: &SavedClient) {
+        let window = saved.get_window();
+        let Some(geometry) = xmanager.get_window_geometry(window) else {
+            return;
+        };
+        if self.has_client(window) {
+            return;
+        }
+
+        let mut client = Client::new(window, geometry.x, geometry.y, geometry.height, geometry.width);
+        client.tags = Some(saved.tags & self.get_all_tags()).filter(|tags| *tags != 0).unwrap_or(self.viewed_tags);
+        client.floating = saved.floating;
+        client.fullscreen = saved.fullscreen;
+        client.float_rect = saved.float_rect;
+        client.size_hints = xmanager.get_size_hints(window);
+        client.reconfigure = true;
+
+        let visible = client.is_visible(self.viewed_tags);
+        xmanager.set_window_desktop(window, self.get_window_desktop(client.tags));
+        if self.focus_model == FocusModel::click {
+            xmanager.grab_focus_buttons(window);
+        }
+        self.layout.position_new_client(client, self.viewed_tags);
+
+        // hidden clients were already unmapped by the previous process
+        if visible {
+            xmanager.map_window(window);
+        }
+        else {
+            xmanager.select_client_events(window);
+            xmanager.set_wm_state(window, WmState::Iconic);
+        }
+        self.reconfigure_clients(xmanager);
+    }
+
+    fn set_geometry(&mut self, xmanager: &Xmanager, rect: Rect) {
+        self.posX = rect.x;
+        self.posY = rect.y;
+        self.width = rect.width;
+        self.height = rect.height;
+        self.layout.set_size(rect.width, rect.height, self.viewed_tags);
+
+        for i in 0..self.layout.clients.len() {
+            let mut float_rect = self.layout.clients[i].float_rect;
+            if self.layout.clients[i].floating && !self.contains_point(float_rect.x, float_rect.y) {
+                self.center_on_monitor(&mut float_rect);
+                self.layout.clients[i].float_rect = float_rect;
+            }
+            self.layout.clients[i].reconfigure = true;
+        }
+        self.reconfigure_clients(xmanager);
+    }
+
+    /// Takes over the clients of a removed monitor, keeping their workspaces.
+    fn receive_clients(&mut self, xmanager: &Xmanager, clients: Vec<Client>, old_tags: u32) {
+        for mut client in clients {
+            let was_visible = client.is_visible(old_tags);
+            let visible = client.is_visible(self.viewed_tags);
+            if was_visible && !visible {
+                client.ignore_unmaps += 1;
+                xmanager.unmap_window(client.window);
+            }
+            else if !was_visible && visible {
+                xmanager.map_window(client.window);
+            }
+            if client.floating && !self.contains_point(client.float_rect.x, client.float_rect.y) {
+                self.center_on_monitor(&mut client.float_rect);
+            }
+            client.reconfigure = true;
+            self.layout.clients.push(client);
+        }
+
+        self.layout.reorder_clients(self.viewed_tags);
+        self.reconfigure_clients(xmanager);
+        self.focus_visible_client(xmanager);
+    }
+
+    fn get_rect(&self) -> Rect {
+        Rect::new(self.posX, self.posY, self.width, self.height)
+    }
+
+    pub fn map_client(&mut self, xmanager: &Xmanager, mut client: Client) {
+
+        client.tags = self.viewed_tags;
+        client.size_hints = xmanager.get_size_hints(client.window);
+
+        // transients belong to their parent, e.g. a dialog should show up where its application is
+        let transient_for = xmanager.get_transient_for(client.window);
+        if let Some(parent) = transient_for.and_then(|parent| self.layout.clients.iter().find(|client| client.window == parent)) {
+            client.tags = parent.tags;
+        }
+
+        client.fullscreen = xmanager.is_fullscreen_state(client.window);
+
+        // dialogs, transients and fixed size windows can not be tiled sensibly
+        let floating = transient_for.is_some() || client.size_hints.is_fixed() || xmanager.is_floating_window_type(client.window);
+        self.place_new_client(&mut client, floating);
+
+        let window = client.window;
+        let visible = client.is_visible(self.viewed_tags);
+        xmanager.set_window_desktop(window, self.get_window_desktop(client.tags));
+        if self.focus_model == FocusModel::click {
+            xmanager.grab_focus_buttons(window);
+        }
+        self.layout.position_new_client(client, self.viewed_tags);
+
+        // a transient of a client on a hidden workspace stays unmapped until that workspace is viewed
+        if !visible {
+            xmanager.select_client_events(window);
+            xmanager.set_wm_state(window, WmState::Iconic);
+            return;
+        }
+
+        xmanager.map_window(window);
+
+        self.reconfigure_clients(xmanager);
+        self.set_focus(xmanager, Some(window));
+    }
+
+    /// The layout only positions tiled clients, new floating clients are centered and
+    /// floating and fullscreen clients are configured here.
+    fn place_new_client(&self, client: &mut Client, floating: bool) {
+        if floating {
+            client.floating = true;
+            self.center_on_monitor(&mut client.float_rect);
+        }
+        if client.floating || client.fullscreen {
+            client.reconfigure = true;
+        }
+    }
+
+    /// Adds a client sent from another monitor to the viewed workspace.
+    pub fn add_client(&mut self, xmanager: &Xmanager, mut client: Client) {
+        client.tags = self.viewed_tags;
+        client.reconfigure = true;
+        if client.floating && !self.contains_point(client.float_rect.x, client.float_rect.y) {
+            self.center_on_monitor(&mut client.float_rect);
+        }
+
+        let window = client.window;
+        xmanager.set_window_desktop(window, self.get_window_desktop(client.tags));
+        // set_focus leaves the window alone on an inactive monitor, so it would keep looking focused
+        if !self.active {
+            xmanager.set_border_color(window, self.appearance.border_color_unfocused);
+            if self.focus_model == FocusModel::click {
+                xmanager.grab_focus_buttons(window);
+            }
+        }
+        self.layout.position_new_client(client, self.viewed_tags);
+        self.reconfigure_clients(xmanager);
+        self.set_focus(xmanager, Some(window));
+    }
+
+    /// Removes a client to hand it over to another monitor, its window stays mapped.
+    pub fn take_client(&mut self, xmanager: &Xmanager, window: x::Window) -> Option<Client> {
+        let i = self.layout.clients.iter().position(|client| client.window == window)?;
+        let client = self.layout.remove_client(i, self.viewed_tags);
+        if self.focused == Some(window) {
+            self.focused = None;
+        }
+        if self.drag.as_ref().is_some_and(|drag| drag.window == window) {
+            self.drag = None;
+        }
+
+        self.focus_visible_client(xmanager);
+        self.reconfigure_clients(xmanager);
+        Some(client)
+    }
+
+    pub fn has_client(&self, window: x::Window) -> bool {
+        self.layout.clients.iter().any(|client| client.window == window)
+    }
+
+    pub fn is_client_visible(&self, window: x::Window) -> bool {
+        self.layout.clients.iter().any(|client| client.window == window && client.is_visible(self.viewed_tags))
+    }
+
+    /// Returns how many pixels of rect lie on this monitor.
+    pub fn get_overlap(&self, rect: Rect) -> u32 {
+        let width = (rect.x as i32 + rect.width as i32).min(self.posX as i32 + self.width as i32) - (rect.x as i32).max(self.posX as i32);
+        let height = (rect.y as i32 + rect.height as i32).min(self.posY as i32 + self.height as i32) - (rect.y as i32).max(self.posY as i32);
+        width.max(0) as u32 * height.max(0) as u32
+    }
+
+    pub fn contains_point(&self, x: i16, y: i16) -> bool {
+        x >= self.posX && (x as i32) < self.posX as i32 + self.width as i32
+            && y >= self.posY && (y as i32) < self.posY as i32 + self.height as i32
+    }
+
+    /// Hands the input focus to this monitor or takes it away, the focused client is kept either way.
+    pub fn set_active(&mut self, xmanager: &Xmanager, active: bool) {
+        self.active = active;
+        if active {
+            xmanager.set_current_desktop(self.get_current_desktop());
+        }
+        self.focus_visible_client(xmanager);
+    }
+
+    pub fn warp_to_focused(&self, xmanager: &Xmanager) {
+        if let Some(window) = self.focused.filter(|_| self.warp_pointer) {
+            xmanager.warp_pointer(window);
+        }
+    }
+
+    /// Handles an UnmapNotify. Unmaps caused by hiding a workspace are counted and ignored,
+    /// any other unmap or a synthetic one (a client withdrawing a hidden window) removes the client.
+    pub fn unmap_window(&mut self, xmanager: &Xmanager, window: x::Window, synthetic: bool) {
+        let Some(client) = self.layout.clients.iter_mut().find(|client| client.window == window) else {
+            return;
+        };
+
+        if client.ignore_unmaps > 0 && !synthetic {
+            client.ignore_unmaps -= 1;
+            return;
+        }
+        self.remove_window(xmanager, window);
+    }
+
+    /// Stops managing a window that was withdrawn or destroyed.
+    pub fn remove_window(&mut self, xmanager: &Xmanager, window: x::Window) {
+        let Some(i) = self.layout.clients.iter().position(|client| client.window == window) else {
+            return;
+        };
+
+        let client = self.layout.remove_client(i, self.viewed_tags);
+        if self.focused == Some(window) {
+            self.focused = None;
+        }
+        if self.drag.as_ref().is_some_and(|drag| drag.window == window) {
+            self.drag = None;
+        }
+        xmanager.remove_wm_state(window);
+
+        if client.is_visible(self.viewed_tags) {
+            self.focus_visible_client(xmanager);
+            self.reconfigure_clients(xmanager);
+        }
+    }
+
+    /// Views a workspace of the client if it is hidden, then focuses and raises it.
+    /// Returns false if the client is not on this monitor or it is hidden and switching is not allowed.
+    pub fn activate_client(&mut self, xmanager: &Xmanager, window: x::Window, allow_switch: bool) -> bool {
+        let Some(client) = self.layout.clients.iter().find(|client| client.window == window) else {
+            return false;
+        };
+        let (tags, floating) = (client.tags, client.floating);
+
+        if !client.is_visible(self.viewed_tags) {
+            if !allow_switch {
+                return false;
+            }
+            self.view_tags(xmanager, 1 << tags.trailing_zeros());
+        }
+
+        self.set_focus(xmanager, Some(window));
+        if floating {
+            xmanager.raise_window(window);
+        }
+        true
+    }
+
+    pub fn update_size_hints(&mut self, xmanager: &Xmanager, window: x::Window) {
+        let Some(client) = self.layout.clients.iter_mut().find(|client| client.window == window) else {
+            return;
+        };
+        client.size_hints = xmanager.get_size_hints(window);
+        client.reconfigure = true;
+        self.reconfigure_clients(xmanager);
+    }
+
+    pub fn focus_client(&mut self, xmanager: &Xmanager, window: x::Window) {
+        if self.is_client_visible(window) {
+            self.set_focus(xmanager, Some(window));
+        }
+    }
+
+    /// Focuses the next or previous visible client in layout order.
+    pub fn focus_adjacent(&mut self, xmanager: &Xmanager, forward: bool) {
+        let Some(window) = self.focused else {
+            return self.focus_visible_client(xmanager);
+        };
+        if let Some(next) = self.layout.get_adjacent_client(window, forward, self.viewed_tags) {
+            self.set_focus(xmanager, Some(next));
+        }
+    }
+
+    pub fn focus_direction(&mut self, xmanager: &Xmanager, direction: Direction) {
+        let Some(window) = self.focused else {
+            return;
+        };
+        if let Some(next) = self.layout.get_client_in_direction(window, direction, self.viewed_tags) {
+            self.set_focus(xmanager, Some(next));
+        }
+    }
+
+    pub fn swap_focused_with_master(&mut self, xmanager: &Xmanager) {
+        if let Some(window) = self.focused {
+            self.layout.swap_with_master(window, self.viewed_tags);
+            self.reconfigure_clients(xmanager);
+        }
+    }
+
+    pub fn move_focused(&mut self, xmanager: &Xmanager, up: bool) {
+        if let Some(window) = self.focused {
+            self.layout.move_client(window, up, self.viewed_tags);
+            self.reconfigure_clients(xmanager);
+        }
+    }
+
+    pub fn set_tiling(&mut self, xmanager: &Xmanager, tiling: Box<dyn Tiling>) {
+        info!("switching layout from {} to {}", self.layout.get_tiling_name(), tiling.name());
+        self.layout.set_tiling(tiling, self.viewed_tags);
+        self.reconfigure_clients(xmanager);
+    }
+
+    pub fn change_master_factor(&mut self, xmanager: &Xmanager, delta: f32) {
+        self.layout.change_master_factor(delta, self.viewed_tags);
+        self.reconfigure_clients(xmanager);
+    }
+
+    pub fn change_master_count(&mut self, xmanager: &Xmanager, delta: i32) {
+        self.layout.change_master_count(delta, self.viewed_tags);
+        self.reconfigure_clients(xmanager);
+    }
+
+    pub fn get_workspace_tag(&self, name: &str) -> Option<u32> {
+        self.workspace_names.iter().position(|workspace_name| workspace_name == name).map(|i| 1 << i)
+    }
+
+    pub fn view_tags(&mut self, xmanager: &Xmanager, tags: u32) {
+        if tags == 0 || tags == self.viewed_tags {
+            return;
+        }
+
+        let old_tags = self.viewed_tags;
+        self.viewed_tags = tags;
+        if self.active {
+            xmanager.set_current_desktop(self.get_current_desktop());
+        }
+        self.update_visibility(xmanager, old_tags);
+    }
+
+    /// EWMH desktops map to single tags, a tag mask is reported as its lowest desktop.
+    fn get_current_desktop(&self) -> u32 {
+        self.viewed_tags.trailing_zeros()
+    }
+
+    // like get_current_desktop, but clients on every tag are shown on all desktops
+    fn get_window_desktop(&self, tags: u32) -> u32 {
+        if tags == self.get_all_tags() && self.workspace_names.len() > 1 {
+            return ALL_DESKTOPS;
+        }
+        tags.trailing_zeros()
+    }
+
+    pub fn get_tags_from_desktop(&self, desktop: u32) -> Option<u32> {
+        if desktop == ALL_DESKTOPS {
+            return Some(self.get_all_tags());
+        }
+        if desktop as usize >= self.workspace_names.len() {
+            return None;
+        }
+        Some(1 << desktop)
+    }
+
+    fn get_all_tags(&self) -> u32 {
+        u32::MAX >> (32 - self.workspace_names.len())
+    }
+
+    pub fn toggle_view_tags(&mut self, xmanager: &Xmanager, tags: u32) {
+        self.view_tags(xmanager, self.viewed_tags ^ tags);
+    }
+
+    pub fn set_focused_tags(&mut self, xmanager: &Xmanager, tags: u32) {
+        if let Some(window) = self.focused {
+            self.set_client_tags(xmanager, window, tags);
+        }
+    }
+
+    pub fn set_client_tags(&mut self, xmanager: &Xmanager, window: x::Window, tags: u32) {
+        let desktop = self.get_window_desktop(tags);
+        let Some(client) = self.layout.clients.iter_mut().find(|client| client.window == window) else {
+            return;
+        };
+        if tags == 0 || tags == client.tags {
+            return;
+        }
+
+        let was_visible = client.is_visible(self.viewed_tags);
+        client.tags = tags;
+        xmanager.set_window_desktop(window, desktop);
+        if was_visible && !client.is_visible(self.viewed_tags) {
+            client.ignore_unmaps += 1;
+            xmanager.unmap_window(client.window);
+            if self.focused == Some(window) {
+                self.focused = None;
             }
         }
+        else if !was_visible && client.is_visible(self.viewed_tags) {
+            xmanager.map_window(client.window);
+        }
+
+        self.layout.reorder_clients(self.viewed_tags);
+        self.reconfigure_clients(xmanager);
+        self.focus_visible_client(xmanager);
     }
 
-    pub fn map_client(&mut self, xmanager: &Xmanager, client: Client) {
+    pub fn toggle_focused_tags(&mut self, xmanager: &Xmanager, tags: u32) {
+        if let Some(i) = self.get_focused_index() {
+            let tags = self.layout.clients[i].tags ^ tags;
+            self.set_focused_tags(xmanager, tags);
+        }
+    }
 
-        xmanager.map_window(client.window);
+    pub fn get_focused(&self) -> Option<x::Window> {
+        self.focused
+    }
 
-        xmanager.focus_window(client.window);
+    fn get_focused_index(&self) -> Option<usize> {
+        let window = self.focused?;
+        self.layout.clients.iter().position(|client| client.window == window)
+    }
 
-        let client = self.layout.position_new_client(client);
+    /// Maps clients that became visible and unmaps the ones that got hidden after the viewed tags changed.
+    fn update_visibility(&mut self, xmanager: &Xmanager, old_tags: u32) {
+        for client in self.layout.clients.iter_mut() {
+            let was_visible = client.is_visible(old_tags);
+            let is_visible = client.is_visible(self.viewed_tags);
+            if was_visible && !is_visible {
+                client.ignore_unmaps += 1;
+                xmanager.unmap_window(client.window);
+            }
+            else if !was_visible && is_visible {
+                xmanager.map_window(client.window);
+            }
+        }
 
+        self.layout.reorder_clients(self.viewed_tags);
         self.reconfigure_clients(xmanager);
 
+        if !self.get_focused_index().is_some_and(|i| self.layout.clients[i].is_visible(self.viewed_tags)) {
+            self.focused = None;
+        }
+        self.focus_visible_client(xmanager);
+    }
+
+    fn focus_visible_client(&mut self, xmanager: &Xmanager) {
+        let window = self.focused.or(self.layout.get_client_to_focus(self.viewed_tags).map(|client| client.window));
+        self.set_focus(xmanager, window);
     }
-    pub fn unmap_window(&mut self, xmanager: &Xmanager, window: x::Window) {
 
-        for (i, client) in self.layout.clients.iter().enumerate() {
-            if client.window == window {
-                self.layout.remove_client(i);
-                break;
+    fn set_focus(&mut self, xmanager: &Xmanager, window: Option<x::Window>) {
+        let changed = self.focused != window;
+        if let Some(old_window) = self.focused {
+            if (Some(old_window) != window || !self.active) && self.has_client(old_window) {
+                xmanager.set_border_color(old_window, self.appearance.border_color_unfocused);
+                if self.focus_model == FocusModel::click {
+                    xmanager.grab_focus_buttons(old_window);
+                }
+            }
+        }
+
+        self.focused = window;
+        if !self.active {
+            return;
+        }
+        match window {
+            Some(window) => {
+                xmanager.set_border_color(window, self.appearance.border_color_focused);
+                // clicks on the focused client go straight to it
+                if self.focus_model == FocusModel::click {
+                    xmanager.ungrab_focus_buttons(window);
+                }
+                xmanager.focus_window(window);
+                if changed {
+                    self.restack(xmanager);
+                }
+                if self.warp_pointer && changed {
+                    xmanager.warp_pointer(window);
+                }
             }
+            None => xmanager.focus_root(),
+        }
+    }
+
+    pub fn toggle_floating(&mut self, xmanager: &Xmanager) {
+        if let Some(i) = self.get_focused_index() {
+            let floating = !self.layout.clients[i].floating;
+            self.set_floating(xmanager, i, floating);
+        }
+    }
+
+    fn set_floating(&mut self, xmanager: &Xmanager, client_index: usize, floating: bool) {
+        if self.layout.clients[client_index].floating == floating {
+            return;
+        }
+
+        if floating {
+            // start floating where the client was tiled
+            let tiled_count = self.get_tiled_count();
+            let (rect, _) = self.get_tiled_geometry(&self.layout.clients[client_index], tiled_count);
+            self.layout.clients[client_index].float_rect = rect;
+        }
+        self.layout.clients[client_index].floating = floating;
+        self.layout.clients[client_index].reconfigure = true;
+
+        self.layout.reorder_clients(self.viewed_tags);
+        self.reconfigure_clients(xmanager);
+    }
+
+    pub fn set_fullscreen(&mut self, xmanager: &Xmanager, window: x::Window, action: StateAction) {
+        let Some(client) = self.layout.clients.iter_mut().find(|client| client.window == window) else {
+            return;
+        };
+        let fullscreen = match action {
+            StateAction::Add => true,
+            StateAction::Remove => false,
+            StateAction::Toggle => !client.fullscreen,
+        };
+        if fullscreen == client.fullscreen {
+            return;
+        }
+
+        info!("fullscreen {:?}: {}", window, fullscreen);
+        client.fullscreen = fullscreen;
+        client.reconfigure = true;
+        xmanager.set_fullscreen_state(window, fullscreen);
+
+        self.layout.reorder_clients(self.viewed_tags);
+        self.reconfigure_clients(xmanager);
+    }
+
+    pub fn get_windows(&self) -> impl Iterator<Item = x::Window> + '_ {
+        self.layout.clients.iter().map(|client| client.window)
+    }
+
+    /// Returns the windows bottom to top in the order restack applies, hidden clients at the bottom.
+    pub fn get_windows_stacking(&self) -> Vec<x::Window> {
+        let hidden = self.layout.clients.iter().filter(|client| !client.is_visible(self.viewed_tags));
+        let visible = self.get_stacking_order().into_iter().map(|(client, _)| client);
+        hidden.chain(visible).map(|client| client.window).collect()
+    }
+
+    fn center_on_monitor(&self, rect: &mut Rect) {
+        let border = self.appearance.border_width as i32;
+        rect.x = (self.posX as i32 + (self.width as i32 - rect.width as i32) / 2 - border) as i16;
+        rect.y = (self.posY as i32 + (self.height as i32 - rect.height as i32) / 2 - border) as i16;
+    }
+
+    pub fn start_drag(&mut self, xmanager: &Xmanager, window: x::Window, action: MouseAction, pointer_x: i16, pointer_y: i16) {
+        let Some(i) = self.layout.clients.iter().position(|client| client.window == window && client.is_visible(self.viewed_tags)) else {
+            return;
+        };
+        if self.layout.clients[i].fullscreen {
+            return;
+        }
+
+        self.set_focus(xmanager, Some(window));
+        self.set_floating(xmanager, i, true);
+        xmanager.raise_window(window);
+
+        self.drag = Some(Drag {
+            window,
+            action,
+            pointer_x,
+            pointer_y,
+            start_rect: self.layout.clients[i].float_rect,
+            last_motion: 0,
+        });
+    }
+
+    pub fn drag_motion(&mut self, xmanager: &Xmanager, pointer_x: i16, pointer_y: i16, time: x::Timestamp) {
+        let Some(drag) = self.drag.as_mut() else {
+            return;
+        };
+        // limit updates to about 60 per second
+        if time.wrapping_sub(drag.last_motion) < 1000 / 60 {
+            return;
         }
+        drag.last_motion = time;
 
-        xmanager.focus_window(self.layout.get_client_to_focus().window);
+        let Some(client) = self.layout.clients.iter_mut().find(|client| client.window == drag.window) else {
+            self.drag = None;
+            return;
+        };
+
+        let dx = pointer_x as i32 - drag.pointer_x as i32;
+        let dy = pointer_y as i32 - drag.pointer_y as i32;
+        let start = drag.start_rect;
+        client.float_rect = match drag.action {
+            MouseAction::Move => Rect::new((start.x as i32 + dx) as i16, (start.y as i32 + dy) as i16, start.width, start.height),
+            MouseAction::Resize => Rect::new(
+                start.x,
+                start.y,
+                (start.width as i32 + dx).clamp(1, u16::MAX as i32) as u16,
+                (start.height as i32 + dy).clamp(1, u16::MAX as i32) as u16,
+            ),
+        };
+        client.reconfigure = true;
 
         self.reconfigure_clients(xmanager);
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    pub fn stop_drag(&mut self) {
+        self.drag = None;
+    }
+
+    fn get_tiled_count(&self) -> usize {
+        self.layout.clients.iter().filter(|client| client.is_tiled(self.viewed_tags)).count()
+    }
+
+    /// Computes the window geometry in root coordinates and the border width of a tiled client.
+    fn get_tiled_geometry(&self, client: &Client, tiled_count: usize) -> (Rect, u16) {
+        let appearance = &self.appearance;
+        let no_gaps = appearance.smart_gaps && tiled_count == 1;
+        let no_border = appearance.smart_borders && tiled_count == 1;
+        let border_width = if no_border { 0 } else { appearance.border_width as i32 };
 
+        let mut x = client.posX as i32;
+        let mut y = client.posY as i32;
+        let mut right = x + client.width as i32;
+        let mut bottom = y + client.height as i32;
+
+        // edges on the monitor border get the outer gap, edges between clients half the inner gap each
+        if !no_gaps {
+            let outer = appearance.outer_gap as i32;
+            let inner = appearance.inner_gap as i32 / 2;
+            x += if x <= 0 { outer } else { inner };
+            y += if y <= 0 { outer } else { inner };
+            right -= if right >= self.width as i32 { outer } else { inner };
+            bottom -= if bottom >= self.height as i32 { outer } else { inner };
+        }
+
+        let width = (right - x - 2 * border_width).max(1);
+        let height = (bottom - y - 2 * border_width).max(1);
+
+        let rect = Rect::new((x + self.posX as i32) as i16, (y + self.posY as i32) as i16, width as u16, height as u16);
+        (rect, border_width as u16)
+    }
+
+    /// Applies a ConfigureRequest of a managed window. Floating clients get the requested geometry,
+    /// the others keep theirs and are told about it with a synthetic ConfigureNotify (ICCCM 4.1.5).
+    pub fn configure_request(&mut self, xmanager: &Xmanager, request: &x::ConfigureRequestEvent) {
+        let Some(i) = self.layout.clients.iter().position(|client| client.window == request.window()) else {
+            return;
+        };
+
+        let client = &mut self.layout.clients[i];
+        if client.floating && !client.fullscreen {
+            let mask = request.value_mask();
+            let rect = &mut client.float_rect;
+            if mask.contains(x::ConfigWindowMask::X) {
+                rect.x = request.x();
+            }
+            if mask.contains(x::ConfigWindowMask::Y) {
+                rect.y = request.y();
+            }
+            if mask.contains(x::ConfigWindowMask::WIDTH) {
+                rect.width = request.width().max(1);
+            }
+            if mask.contains(x::ConfigWindowMask::HEIGHT) {
+                rect.height = request.height().max(1);
+            }
+            client.reconfigure = true;
+            if client.is_visible(self.viewed_tags) {
+                self.reconfigure_clients(xmanager);
+            }
+        }
+
+        // a real ConfigureNotify only follows if the geometry actually changed
+        let client = &self.layout.clients[i];
+        let (rect, border_width) = self.get_client_geometry(client, self.get_tiled_count());
+        xmanager.send_configure_notify(client.window, rect, border_width);
+    }
+
+    /// Computes the window geometry in root coordinates with size hints applied and the border width of a client.
+    fn get_client_geometry(&self, client: &Client, tiled_count: usize) -> (Rect, u16) {
+        if client.fullscreen {
+            return (Rect::new(self.posX, self.posY, self.width, self.height), 0);
+        }
+        let (rect, border_width) = if client.floating {
+            (client.float_rect, self.appearance.border_width)
+        }
+        else {
+            self.get_tiled_geometry(client, tiled_count)
+        };
+        let honor_increments = client.floating || !self.appearance.ignore_tiled_size_increments;
+        let (width, height) = client.size_hints.apply(rect.width as u32, rect.height as u32, honor_increments);
+        (Rect::new(rect.x, rect.y, width as u16, height as u16), border_width)
     }
 
     fn reconfigure_clients(&self, xmanager: &Xmanager) {
+        let tiled_count = self.get_tiled_count();
+
         for client in self.layout.clients.iter() {
-            if client.reconfigure == true {
-                let screen_x = client.posX + self.posX;
-                let screen_y = client.posY + self.posY;
+            if client.reconfigure && client.is_visible(self.viewed_tags) {
+                let (rect, border_width) = self.get_client_geometry(client, tiled_count);
+                xmanager.set_window_configuration(client.window, rect.x as i32, rect.y as i32, rect.width as u32, rect.height as u32, border_width as u32);
+                if Some(client.window) != self.focused {
+                    xmanager.set_border_color(client.window, self.appearance.border_color_unfocused);
+                }
+            }
+        }
+
+        self.restack(xmanager);
+    }
 
-                xmanager.set_window_configuration(client.window, screen_x as i32, screen_y as i32, client.width as u32, client.height as u32);
+    fn restack(&self, xmanager: &Xmanager) {
+        // tiled clients only overlap in stacking layouts, where the focused one is raised
+        for (client, raise) in self.get_stacking_order() {
+            if raise {
+                xmanager.raise_window(client.window);
             }
         }
     }
+
+    /// Returns the visible clients bottom to top: tiled, floating and then fullscreen clients,
+    /// the focused client on top of its group. Clients that have to be raised are marked.
+    fn get_stacking_order(&self) -> Vec<(&Client, bool)> {
+        let mut clients: Vec<(&Client, bool)> = self.layout.clients.iter()
+            .filter(|client| client.is_visible(self.viewed_tags))
+            .map(|client| {
+                let stacked = client.floating || client.fullscreen || self.layout.stacks_clients();
+                (client, stacked && Some(client.window) == self.focused)
+            })
+            .collect();
+        clients.sort_by_key(|(client, focused)| (client.fullscreen, client.floating, *focused));
+        clients.into_iter()
+            .map(|(client, focused)| (client, focused || client.floating || client.fullscreen))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use xcb::XidNew;
+
+    use super::*;
+    use crate::config::FocusStealing;
+
+    fn test_settings() -> Settings {
+        Settings {
+            keymap: HashMap::new(),
+            workspaces: (1..=9).map(|i| i.to_string()).collect(),
+            layout: "master_stack_left".to_owned(),
+            master_factor: 0.5,
+            master_count: 1,
+            appearance: Appearance {
+                inner_gap: 0,
+                outer_gap: 0,
+                border_width: 2,
+                border_color_focused: 0x005577,
+                border_color_unfocused: 0x444444,
+                smart_gaps: false,
+                smart_borders: false,
+                ignore_tiled_size_increments: false,
+            },
+            mouse_modifier: "Mod4".to_owned(),
+            focus_stealing: FocusStealing::allow,
+            focus_model: FocusModel::sloppy,
+            warp_pointer: false,
+            close_timeout: 0,
+        }
+    }
+
+    fn test_monitor() -> Monitor {
+        Monitor::new(randr::Crtc::none(), Rect::new(1920, 0, 1280, 1024), &test_settings(), true)
+    }
+
+    fn add_test_client(monitor: &mut Monitor, window: u32, floating: bool, fullscreen: bool) {
+        let mut client = Client::new(x::Window::new(window), 0, 0, 200, 400);
+        client.tags = monitor.viewed_tags;
+        client.floating = floating;
+        client.fullscreen = fullscreen;
+        monitor.layout.position_new_client(client, monitor.viewed_tags);
+    }
+
+    fn stacking_order(monitor: &Monitor) -> Vec<(u32, bool)> {
+        monitor.get_stacking_order().into_iter().map(|(client, raise)| (client.window.resource_id(), raise)).collect()
+    }
+
+    #[test]
+    fn focused_floating_client_is_raised_last() {
+        let mut monitor = test_monitor();
+        add_test_client(&mut monitor, 1, true, false);
+        add_test_client(&mut monitor, 2, false, false);
+        add_test_client(&mut monitor, 3, true, false);
+        add_test_client(&mut monitor, 4, false, true);
+        monitor.focused = Some(x::Window::new(1));
+
+        assert_eq!(stacking_order(&monitor), vec![(2, false), (3, true), (1, true), (4, true)]);
+    }
+
+    #[test]
+    fn focused_tiled_client_is_raised_in_stacking_layouts() {
+        let mut monitor = test_monitor();
+        add_test_client(&mut monitor, 1, false, false);
+        add_test_client(&mut monitor, 2, false, false);
+        add_test_client(&mut monitor, 3, true, false);
+        monitor.focused = Some(x::Window::new(1));
+        assert_eq!(stacking_order(&monitor), vec![(1, false), (2, false), (3, true)]);
+
+        monitor.layout.set_tiling(tiling::tiling_from_name("monocle").unwrap(), monitor.viewed_tags);
+        assert_eq!(stacking_order(&monitor), vec![(2, false), (1, true), (3, true)]);
+    }
+
+    #[test]
+    fn new_floating_client_is_centered_and_configured() {
+        let monitor = test_monitor();
+        let mut client = Client::new(x::Window::new(0x400001), 0, 0, 200, 400);
+        monitor.place_new_client(&mut client, true);
+
+        assert!(client.floating);
+        assert!(client.reconfigure);
+        assert_eq!(client.float_rect, Rect::new(1920 + 440 - 2, 412 - 2, 400, 200));
+
+        let (rect, border_width) = monitor.get_client_geometry(&client, 0);
+        assert_eq!((rect, border_width), (client.float_rect, 2));
+    }
+
+    #[test]
+    fn new_fullscreen_client_covers_the_monitor() {
+        let monitor = test_monitor();
+        let mut client = Client::new(x::Window::new(0x400001), 0, 0, 200, 400);
+        client.fullscreen = true;
+        monitor.place_new_client(&mut client, false);
+
+        assert!(client.reconfigure);
+        assert_eq!(monitor.get_client_geometry(&client, 0), (Rect::new(1920, 0, 1280, 1024), 0));
+    }
+
+    #[test]
+    fn new_tiled_client_is_left_to_the_layout() {
+        let monitor = test_monitor();
+        let mut client = Client::new(x::Window::new(0x400001), 0, 0, 200, 400);
+        monitor.place_new_client(&mut client, false);
+
+        assert!(!client.floating);
+        assert!(!client.reconfigure);
+        assert_eq!(client.float_rect, Rect::new(0, 0, 400, 200));
+    }
 }
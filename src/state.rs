@@ -0,0 +1,157 @@
+use log::warn;
+use xcb::{x, XidNew};
+
+use crate::tiling::Rect;
+
+// State handed from one dswm process to the next on restart, stored as text in the _DSWM_STATE root property.
+// Every line describes one monitor or client, clients are listed in stack order:
+//   monitor <crtc> <viewed tags> <tiling> <master factor> <master count> <focused window>
+//   client <crtc> <window> <tags> <floating> <fullscreen> <x> <y> <width> <height>
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SavedMonitor {
+    pub crtc: u32,
+    pub viewed_tags: u32,
+    pub tiling: String,
+    pub master_factor: f32,
+    pub master_count: usize,
+    pub focused: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SavedClient {
+    pub crtc: u32,
+    pub window: u32,
+    pub tags: u32,
+    pub floating: bool,
+    pub fullscreen: bool,
+    pub float_rect: Rect,
+}
+
+impl SavedClient {
+    pub fn get_window(&self) -> x::Window {
+        // the window may be gone by now, which the server reports once it is used
+        x::Window::new(self.window)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SavedState {
+    pub monitors: Vec<SavedMonitor>,
+    pub clients: Vec<SavedClient>,
+}
+
+impl SavedState {
+    pub fn serialize(&self) -> String {
+        let mut text = String::new();
+        for monitor in &self.monitors {
+            text += &format!("monitor {} {} {} {} {} {}\n",
+                monitor.crtc, monitor.viewed_tags, monitor.tiling, monitor.master_factor, monitor.master_count, monitor.focused);
+        }
+        for client in &self.clients {
+            let rect = client.float_rect;
+            text += &format!("client {} {} {} {} {} {} {} {} {}\n",
+                client.crtc, client.window, client.tags, client.floating as u8, client.fullscreen as u8,
+                rect.x, rect.y, rect.width, rect.height);
+        }
+        text
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut state = SavedState::default();
+        for line in text.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let parsed = match fields.first() {
+                Some(&"monitor") => Self::parse_monitor(&fields[1..]).map(|monitor| state.monitors.push(monitor)),
+                Some(&"client") => Self::parse_client(&fields[1..]).map(|client| state.clients.push(client)),
+                _ => None,
+            };
+            if parsed.is_none() {
+                warn!("ignoring malformed saved state line {:?}", line);
+            }
+        }
+        state
+    }
+
+    fn parse_monitor(fields: &[&str]) -> Option<SavedMonitor> {
+        let [crtc, viewed_tags, tiling, master_factor, master_count, focused] = fields else {
+            return None;
+        };
+        Some(SavedMonitor {
+            crtc: crtc.parse().ok()?,
+            viewed_tags: viewed_tags.parse().ok()?,
+            tiling: tiling.to_string(),
+            master_factor: master_factor.parse().ok()?,
+            master_count: master_count.parse().ok()?,
+            focused: focused.parse().ok()?,
+        })
+    }
+
+    fn parse_client(fields: &[&str]) -> Option<SavedClient> {
+        let [crtc, window, tags, floating, fullscreen, x, y, width, height] = fields else {
+            return None;
+        };
+        Some(SavedClient {
+            crtc: crtc.parse().ok()?,
+            window: window.parse().ok()?,
+            tags: tags.parse().ok()?,
+            floating: *floating == "1",
+            fullscreen: *fullscreen == "1",
+            float_rect: Rect::new(x.parse().ok()?, y.parse().ok()?, width.parse().ok()?, height.parse().ok()?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_state() -> SavedState {
+        SavedState {
+            monitors: vec![
+                SavedMonitor { crtc: 63, viewed_tags: 0b101, tiling: "master_stack_left".to_string(), master_factor: 0.6, master_count: 2, focused: 0x600003 },
+                SavedMonitor { crtc: 64, viewed_tags: 1, tiling: "monocle".to_string(), master_factor: 0.5, master_count: 1, focused: 0 },
+            ],
+            clients: vec![
+                SavedClient { crtc: 63, window: 0x600003, tags: 0b100, floating: false, fullscreen: false, float_rect: Rect::new(0, 0, 800, 600) },
+                SavedClient { crtc: 63, window: 0x800001, tags: 1, floating: true, fullscreen: false, float_rect: Rect::new(-120, -40, 640, 480) },
+                SavedClient { crtc: 64, window: 0xa00007, tags: 1, floating: true, fullscreen: true, float_rect: Rect::new(-1920, 15, 300, 200) },
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let state = sample_state();
+        assert_eq!(SavedState::parse(&state.serialize()), state);
+    }
+
+    #[test]
+    fn empty_state() {
+        assert_eq!(SavedState::parse(""), SavedState::default());
+        assert_eq!(SavedState::parse(&SavedState::default().serialize()), SavedState::default());
+    }
+
+    #[test]
+    fn skips_malformed_lines() {
+        let text = "\
+            monitor 63 1 monocle 0.5 1\n\
+            monitor 63 1 monocle 0.5 1 0 7\n\
+            monitor 63 one monocle 0.5 1 0\n\
+            client 63 4194305 1 0 0 10 10 100\n\
+            client 63 4194305 1 0 0 10 10 100 100 5\n\
+            client 63 4194305 1 0 0 10 -10.5 100 100\n\
+            client 63 4194305 1 0 0 10 10 -100 100\n\
+            window 63 4194305\n\
+            \n\
+            monitor 64 2 grid 0.4 0 0\n\
+            client 64 4194306 2 1 0 -5 -6 70 80\n";
+        let state = SavedState::parse(text);
+        assert_eq!(state.monitors, vec![
+            SavedMonitor { crtc: 64, viewed_tags: 2, tiling: "grid".to_string(), master_factor: 0.4, master_count: 0, focused: 0 },
+        ]);
+        assert_eq!(state.clients, vec![
+            SavedClient { crtc: 64, window: 4194306, tags: 2, floating: true, fullscreen: false, float_rect: Rect::new(-5, -6, 70, 80) },
+        ]);
+    }
+}
@@ -0,0 +1,312 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i16,
+    pub y: i16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Rect {
+    pub fn new(x: i16, y: i16, width: u16, height: u16) -> Self {
+        Rect { x, y, width, height }
+    }
+
+    // splits the rect into count columns, the remainder is spread so no pixel is lost
+    fn split_columns(&self, count: usize) -> Vec<Rect> {
+        split(self.width, count).into_iter()
+            .map(|(offset, width)| Rect::new(self.x + offset as i16, self.y, width, self.height))
+            .collect()
+    }
+
+    fn split_rows(&self, count: usize) -> Vec<Rect> {
+        split(self.height, count).into_iter()
+            .map(|(offset, height)| Rect::new(self.x, self.y + offset as i16, self.width, height))
+            .collect()
+    }
+}
+
+fn split(length: u16, count: usize) -> Vec<(u16, u16)> {
+    (0..count).map(|i| {
+        let start = (length as usize * i / count) as u16;
+        let end = (length as usize * (i + 1) / count) as u16;
+        (start, end - start)
+    }).collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MasterArea {
+    // share of the area taken by the master clients
+    pub factor: f32,
+    pub count: usize,
+}
+
+/// A tiling algorithm. The rects are returned in layout order, master clients first.
+pub trait Tiling {
+    fn name(&self) -> &'static str;
+    fn arrange(&self, area: Rect, client_count: usize, master: MasterArea) -> Vec<Rect>;
+
+    /// Whether the tiled clients overlap, so the focused one has to be raised to be seen.
+    fn stacks_clients(&self) -> bool {
+        false
+    }
+}
+
+pub fn tiling_from_name(name: &str) -> Option<Box<dyn Tiling>> {
+    let tiling: Box<dyn Tiling> = match name {
+        "master_stack_left" => Box::new(MasterStack { side: Side::Left }),
+        "master_stack_right" => Box::new(MasterStack { side: Side::Right }),
+        "master_stack_top" => Box::new(MasterStack { side: Side::Top }),
+        "master_stack_bottom" => Box::new(MasterStack { side: Side::Bottom }),
+        "monocle" => Box::new(Monocle),
+        "grid" => Box::new(Grid),
+        "spiral" | "fibonacci" => Box::new(Spiral),
+        "centered_master" => Box::new(CenteredMaster),
+        _ => return None,
+    };
+    Some(tiling)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Side {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+struct MasterStack {
+    side: Side,
+}
+
+impl Tiling for MasterStack {
+    fn name(&self) -> &'static str {
+        match self.side {
+            Side::Left => "master_stack_left",
+            Side::Right => "master_stack_right",
+            Side::Top => "master_stack_top",
+            Side::Bottom => "master_stack_bottom",
+        }
+    }
+
+    fn arrange(&self, area: Rect, client_count: usize, master: MasterArea) -> Vec<Rect> {
+        let master_count = master.count.min(client_count);
+        let stack_count = client_count - master_count;
+        let vertical = matches!(self.side, Side::Left | Side::Right);
+
+        if master_count == 0 || stack_count == 0 {
+            return if vertical { area.split_rows(client_count) } else { area.split_columns(client_count) };
+        }
+
+        let master_width = ((area.width as f32 * master.factor) as u16).min(area.width);
+        let master_height = ((area.height as f32 * master.factor) as u16).min(area.height);
+        let (master_rect, stack_rect) = match self.side {
+            Side::Left => (
+                Rect::new(area.x, area.y, master_width, area.height),
+                Rect::new(area.x + master_width as i16, area.y, area.width.saturating_sub(master_width), area.height),
+            ),
+            Side::Right => (
+                Rect::new(area.x + area.width.saturating_sub(master_width) as i16, area.y, master_width, area.height),
+                Rect::new(area.x, area.y, area.width.saturating_sub(master_width), area.height),
+            ),
+            Side::Top => (
+                Rect::new(area.x, area.y, area.width, master_height),
+                Rect::new(area.x, area.y + master_height as i16, area.width, area.height.saturating_sub(master_height)),
+            ),
+            Side::Bottom => (
+                Rect::new(area.x, area.y + area.height.saturating_sub(master_height) as i16, area.width, master_height),
+                Rect::new(area.x, area.y, area.width, area.height.saturating_sub(master_height)),
+            ),
+        };
+
+        if vertical {
+            let mut rects = master_rect.split_rows(master_count);
+            rects.extend(stack_rect.split_rows(stack_count));
+            rects
+        }
+        else {
+            let mut rects = master_rect.split_columns(master_count);
+            rects.extend(stack_rect.split_columns(stack_count));
+            rects
+        }
+    }
+}
+
+struct Monocle;
+
+impl Tiling for Monocle {
+    fn name(&self) -> &'static str {
+        "monocle"
+    }
+
+    fn arrange(&self, area: Rect, client_count: usize, _master: MasterArea) -> Vec<Rect> {
+        vec![area; client_count]
+    }
+
+    fn stacks_clients(&self) -> bool {
+        true
+    }
+}
+
+struct Grid;
+
+impl Tiling for Grid {
+    fn name(&self) -> &'static str {
+        "grid"
+    }
+
+    fn arrange(&self, area: Rect, client_count: usize, _master: MasterArea) -> Vec<Rect> {
+        if client_count == 0 {
+            return Vec::new();
+        }
+
+        let mut columns = 1;
+        while columns * columns < client_count {
+            columns += 1;
+        }
+        let rows = client_count.div_ceil(columns);
+
+        let mut rects = Vec::new();
+        for (row, row_rect) in area.split_rows(rows).into_iter().enumerate() {
+            // the last row takes whatever is left and stretches it over the full width
+            let row_count = columns.min(client_count - row * columns);
+            rects.extend(row_rect.split_columns(row_count));
+        }
+        rects
+    }
+}
+
+struct Spiral;
+
+impl Tiling for Spiral {
+    fn name(&self) -> &'static str {
+        "spiral"
+    }
+
+    fn arrange(&self, area: Rect, client_count: usize, _master: MasterArea) -> Vec<Rect> {
+        let mut rects = Vec::new();
+        let mut remaining = area;
+
+        for i in 0..client_count {
+            if i == client_count - 1 {
+                rects.push(remaining);
+                break;
+            }
+
+            // each client takes half of the remaining space, turning clockwise
+            let (client, rest) = match i % 4 {
+                0 => {
+                    let halves = remaining.split_columns(2);
+                    (halves[0], halves[1])
+                }
+                1 => {
+                    let halves = remaining.split_rows(2);
+                    (halves[0], halves[1])
+                }
+                2 => {
+                    let halves = remaining.split_columns(2);
+                    (halves[1], halves[0])
+                }
+                _ => {
+                    let halves = remaining.split_rows(2);
+                    (halves[1], halves[0])
+                }
+            };
+            rects.push(client);
+            remaining = rest;
+        }
+        rects
+    }
+}
+
+struct CenteredMaster;
+
+impl Tiling for CenteredMaster {
+    fn name(&self) -> &'static str {
+        "centered_master"
+    }
+
+    fn arrange(&self, area: Rect, client_count: usize, master: MasterArea) -> Vec<Rect> {
+        let master_count = master.count.min(client_count);
+        let stack_count = client_count - master_count;
+        if master_count == 0 || stack_count <= 1 {
+            return MasterStack { side: Side::Left }.arrange(area, client_count, master);
+        }
+
+        let master_width = ((area.width as f32 * master.factor) as u16).min(area.width);
+        let left_width = area.width.saturating_sub(master_width) / 2;
+        let right_width = area.width.saturating_sub(master_width).saturating_sub(left_width);
+        let left = Rect::new(area.x, area.y, left_width, area.height);
+        let center = Rect::new(area.x + left_width as i16, area.y, master_width, area.height);
+        let right = Rect::new(area.x + (left_width + master_width) as i16, area.y, right_width, area.height);
+
+        // stack clients alternate between the right and the left column
+        let mut right_rects = right.split_rows(stack_count.div_ceil(2)).into_iter();
+        let mut left_rects = left.split_rows(stack_count / 2).into_iter();
+
+        let mut rects = center.split_rows(master_count);
+        for i in 0..stack_count {
+            let rect = if i % 2 == 0 { right_rects.next() } else { left_rects.next() };
+            rects.extend(rect);
+        }
+        rects
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TILINGS: [&str; 8] = [
+        "master_stack_left", "master_stack_right", "master_stack_top", "master_stack_bottom",
+        "monocle", "grid", "spiral", "centered_master",
+    ];
+
+    fn contains(area: Rect, rect: Rect) -> bool {
+        rect.x >= area.x && rect.y >= area.y
+            && rect.x as i32 + rect.width as i32 <= area.x as i32 + area.width as i32
+            && rect.y as i32 + rect.height as i32 <= area.y as i32 + area.height as i32
+    }
+
+    #[test]
+    fn arrange_fills_area() {
+        let areas = [Rect::new(0, 0, 1920, 1080), Rect::new(-1280, 20, 1280, 1004), Rect::new(7, 3, 5, 2)];
+        for name in TILINGS {
+            let tiling = tiling_from_name(name).unwrap();
+            assert_eq!(tiling.name(), name);
+            for area in areas {
+                for client_count in [0, 1, 2, 3, 5, 8] {
+                    for master_count in [0, 1, 2, 10] {
+                        let master = MasterArea { factor: 0.6, count: master_count };
+                        let rects = tiling.arrange(area, client_count, master);
+                        assert_eq!(rects.len(), client_count, "{name} with {client_count} clients and {master_count} masters");
+                        for rect in rects {
+                            assert!(contains(area, rect), "{name}: {rect:?} outside of {area:?}");
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn arrange_extreme_factors() {
+        let area = Rect::new(0, 0, 1920, 1080);
+        for name in TILINGS {
+            let tiling = tiling_from_name(name).unwrap();
+            for factor in [0.0, 1.0] {
+                let rects = tiling.arrange(area, 4, MasterArea { factor, count: 1 });
+                assert_eq!(rects.len(), 4);
+                assert!(rects.iter().all(|rect| contains(area, *rect)), "{name} with factor {factor}");
+            }
+        }
+    }
+
+    #[test]
+    fn single_client_takes_whole_area() {
+        let area = Rect::new(10, 20, 800, 600);
+        for name in TILINGS {
+            let rects = tiling_from_name(name).unwrap().arrange(area, 1, MasterArea { factor: 0.5, count: 1 });
+            assert_eq!(rects, vec![area], "{name}");
+        }
+    }
+}
@@ -1,19 +1,27 @@
+use std::collections::{HashMap, VecDeque};
 use std::process;
+use std::os::fd::AsRawFd;
+use std::time::Instant;
 
-use log::{debug, error, info, trace, warn};
-use log4rs;
+use log::{debug, error, info};
 
 use xcb::x::CURRENT_TIME;
 use xcb::{x, Connection, xkb};
 use xcb::{Xid};
 
 use crate::WindowConfiguration;
+use crate::client::SizeHints;
+use crate::tiling::Rect;
+
+const XK_NUM_LOCK: x::Keysym = 0xff7f;
 
 struct WmAtoms {
     wm_protocols: xcb::x::Atom,
     wm_delete_window: xcb::x::Atom,
     wm_state: xcb::x::Atom,
     wm_take_focus: xcb::x::Atom,
+    utf8_string: xcb::x::Atom,
+    dswm_state: xcb::x::Atom,
 }
 struct NetAtoms {
     net_active_window: xcb::x::Atom,
@@ -22,13 +30,24 @@ struct NetAtoms {
     net_wm_state: xcb::x::Atom,
     net_supporting_wm_check: xcb::x::Atom,
     net_wm_state_fullscreen: xcb::x::Atom,
-    net_wm_state_window_type: xcb::x::Atom,
+    net_wm_window_type: xcb::x::Atom,
     net_wm_window_type_dialog: xcb::x::Atom,
     net_client_list: xcb::x::Atom,
+    net_wm_window_type_utility: xcb::x::Atom,
+    net_wm_window_type_splash: xcb::x::Atom,
+    net_wm_window_type_toolbar: xcb::x::Atom,
+    net_wm_window_type_normal: xcb::x::Atom,
+    net_client_list_stacking: xcb::x::Atom,
+    net_number_of_desktops: xcb::x::Atom,
+    net_desktop_names: xcb::x::Atom,
+    net_current_desktop: xcb::x::Atom,
+    net_wm_desktop: xcb::x::Atom,
+    net_desktop_viewport: xcb::x::Atom,
+    net_close_window: xcb::x::Atom,
 }
 
 impl NetAtoms {
-    fn as_array(&self) -> [x::Atom; 9] {
+    fn as_array(&self) -> [x::Atom; 20] {
         [
             self.net_active_window,
             self.net_supported,
@@ -36,22 +55,60 @@ impl NetAtoms {
             self.net_wm_state,
             self.net_supporting_wm_check,
             self.net_wm_state_fullscreen,
-            self.net_wm_state_window_type,
+            self.net_wm_window_type,
             self.net_wm_window_type_dialog,
             self.net_client_list,
+            self.net_wm_window_type_utility,
+            self.net_wm_window_type_splash,
+            self.net_wm_window_type_toolbar,
+            self.net_wm_window_type_normal,
+            self.net_client_list_stacking,
+            self.net_number_of_desktops,
+            self.net_desktop_names,
+            self.net_current_desktop,
+            self.net_wm_desktop,
+            self.net_desktop_viewport,
+            self.net_close_window,
         ]
     }
 }
 
+/// ICCCM WM_STATE values, withdrawn windows have no WM_STATE at all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WmState {
+    Normal = 1,
+    Iconic = 3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateAction {
+    Remove,
+    Add,
+    Toggle,
+}
+
+/// Requests sent by clients or pagers as ClientMessage events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientRequest {
+    Fullscreen { window: x::Window, action: StateAction },
+    CurrentDesktop { desktop: u32 },
+    WindowDesktop { window: x::Window, desktop: u32 },
+    ActiveWindow { window: x::Window, from_pager: bool },
+    CloseWindow { window: x::Window },
+}
+
 pub struct Xmanager {
     pub screen: x::ScreenBuf,
     pub conn: xcb::Connection,
     wm_atoms: WmAtoms,
     net_atoms: NetAtoms,
+    keysym_table: HashMap<x::Keysym, x::Keycode>,
+    // events read from the connection while discarding enter events
+    pending_events: VecDeque<xcb::Event>,
 }
 
 impl WindowConfiguration for Xmanager {
-    fn set_window_configuration(&self, window: x::Window, x: i32, y: i32, width: u32, height: u32) {
+    fn set_window_configuration(&self, window: x::Window, x: i32, y: i32, width: u32, height: u32, border_width: u32) {
         let cookie = self.conn.send_request_checked(&x::ConfigureWindow {
             window,
             value_list: &[
@@ -59,6 +116,7 @@ impl WindowConfiguration for Xmanager {
                 x::ConfigWindow::Y(y),
                 x::ConfigWindow::Width(width),
                 x::ConfigWindow::Height(height),
+                x::ConfigWindow::BorderWidth(border_width),
             ],
         });
         self.check_request(cookie);
@@ -76,11 +134,13 @@ impl Xmanager {
 
         let (wm_atoms, net_atoms) = Self::setup_atoms(&conn);
 
-        let this = Xmanager {
+        let mut this = Xmanager {
             screen: screen.to_owned(), 
             conn,
             wm_atoms,
             net_atoms,
+            keysym_table: HashMap::new(),
+            pending_events: VecDeque::new(),
         };
 
         this.setup_check_window();
@@ -99,6 +159,11 @@ impl Xmanager {
             property: this.net_atoms.net_client_list,
         });
         this.check_request(cookie);
+        let cookie = this.conn.send_request_checked(&x::DeleteProperty {
+            window: this.screen.root(),
+            property: this.net_atoms.net_client_list_stacking,
+        });
+        this.check_request(cookie);
 
 
         let cookie = this.conn.send_request_checked(&x::ChangeWindowAttributes {
@@ -123,55 +188,786 @@ impl Xmanager {
         });
         this.check_request(cookie);
 
-        let cookie = this.conn.send_request(&xkb::GetMap {
+        let cookie = this.conn.send_request(&xkb::UseExtension {
+            wanted_major: 1,
+            wanted_minor: 0,
         });
         let reply = this.conn.wait_for_reply(cookie).unwrap();
+        if !reply.supported() {
+            error!("X server does not support xkb 1.0");
+            process::exit(1);
+        }
+
+        let cookie = this.conn.send_request_checked(&xkb::SelectEvents {
+            device_spec: xkb::Id::UseCoreKbd as xkb::DeviceSpec,
+            affect_which: xkb::EventType::NEW_KEYBOARD_NOTIFY | xkb::EventType::MAP_NOTIFY,
+            clear: xkb::EventType::empty(),
+            select_all: xkb::EventType::NEW_KEYBOARD_NOTIFY | xkb::EventType::MAP_NOTIFY,
+            affect_map: xkb::MapPart::KEY_SYMS,
+            map: xkb::MapPart::KEY_SYMS,
+            details: &[],
+        });
+        this.check_request(cookie);
+
+        this.update_keysym_table();
 
         this
     }
 
-    pub fn map_window(&self, window: x::Window) {
+    /// Returns the events held back by discard_enter_events first, then waits for the next one from the server.
+    /// Gives up with None once the deadline has passed.
+    pub fn next_event(&mut self, deadline: Option<Instant>) -> xcb::Result<Option<xcb::Event>> {
+        if let Some(event) = self.pending_events.pop_front() {
+            return Ok(Some(event));
+        }
+        let Some(deadline) = deadline else {
+            return self.conn.wait_for_event().map(Some);
+        };
+        // xcb has no timed wait, so poll(2) waits on the connection until something arrives or the deadline passes
+        loop {
+            if let Some(event) = self.conn.poll_for_event()? {
+                return Ok(Some(event));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            self.conn.flush()?;
+            let timeout = (deadline - now).as_micros().div_ceil(1000).min(i32::MAX as u128) as i32;
+            let mut fd = libc::pollfd { fd: self.conn.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+            // SAFETY: fd is a single valid pollfd that outlives the call
+            // errors like EINTR just lead to another round, connection errors show up in poll_for_event
+            unsafe { libc::poll(&mut fd, 1, timeout) };
+        }
+    }
+
+    /// Drops enter events caused by mapping, moving or resizing windows under the pointer,
+    /// so rearranging the layout does not move the focus.
+    pub fn discard_enter_events(&mut self) {
+        // the reply arrives after every event caused by the requests sent so far
+        let cookie = self.conn.send_request(&x::GetInputFocus {});
+        if self.conn.wait_for_reply(cookie).is_err() {
+            return;
+        }
+        loop {
+            match self.conn.poll_for_queued_event() {
+                Ok(Some(xcb::Event::X(x::Event::EnterNotify(_)))) => {}
+                Ok(Some(event)) => self.pending_events.push_back(event),
+                Ok(None) => break,
+                Err(err) => error!("X error: {err}"),
+            }
+        }
+    }
+
+    /// Refetches the keysym table, needs to be called whenever the keyboard mapping changes.
+    pub fn update_keysym_table(&mut self) {
+        self.keysym_table = self.fetch_keysym_table();
+    }
+
+    /// Builds a keysym -> keycode lookup from the core keyboards xkb map.
+    /// All shift levels map to the same keycode, keysyms of the first group win over the other groups.
+    fn fetch_keysym_table(&self) -> HashMap<x::Keysym, x::Keycode> {
+        let setup = self.conn.get_setup();
+        let min_keycode = setup.min_keycode();
+        let max_keycode = setup.max_keycode();
+
+        let cookie = self.conn.send_request(&xkb::GetMap {
+            device_spec: xkb::Id::UseCoreKbd as xkb::DeviceSpec,
+            full: xkb::MapPart::KEY_SYMS,
+            partial: xkb::MapPart::empty(),
+            first_type: 0,
+            n_types: 0,
+            first_key_sym: min_keycode,
+            n_key_syms: max_keycode - min_keycode + 1,
+            first_key_action: 0,
+            n_key_actions: 0,
+            first_key_behavior: 0,
+            n_key_behaviors: 0,
+            virtual_mods: xkb::VMod::empty(),
+            first_key_explicit: 0,
+            n_key_explicit: 0,
+            first_mod_map_key: 0,
+            n_mod_map_keys: 0,
+            first_v_mod_map_key: 0,
+            n_v_mod_map_keys: 0,
+        });
+        let reply = self.conn.wait_for_reply(cookie).unwrap();
+
+        // (group, keycode, keysym)
+        let mut entries = Vec::new();
+        for part in reply.map() {
+            if let xkb::GetMapReplyMap::KeySyms(key_sym_maps) = part {
+                for (i, key_sym_map) in key_sym_maps.iter().enumerate() {
+                    let keycode = reply.first_key_sym() + i as x::Keycode;
+                    let group_width = key_sym_map.width() as usize;
+                    if group_width == 0 {
+                        continue;
+                    }
+                    for (group, syms) in key_sym_map.syms().chunks(group_width).enumerate() {
+                        entries.extend(syms.iter().filter(|keysym| **keysym != 0).map(|keysym| (group, keycode, *keysym)));
+                    }
+                }
+            }
+        }
+        entries.sort_by_key(|(group, _, _)| *group);
+
+        let mut keysym_table = HashMap::new();
+        for (_, keycode, keysym) in entries {
+            keysym_table.entry(keysym).or_insert(keycode);
+        }
+        debug!("loaded {} keysyms from xkb map", keysym_table.len());
+        keysym_table
+    }
+
+    pub fn keycode_from_keysym(&self, keysym: x::Keysym) -> Option<x::Keycode> {
+        self.keysym_table.get(&keysym).copied()
+    }
+
+    /// Finds the modifier NumLock is mapped to, in the same way as dwm's updatenumlockmask.
+    pub fn get_numlock_mask(&self) -> x::ModMask {
+        let Some(numlock) = self.keycode_from_keysym(XK_NUM_LOCK) else {
+            return x::ModMask::empty();
+        };
+        let cookie = self.conn.send_request(&x::GetModifierMapping {});
+        let Ok(reply) = self.conn.wait_for_reply(cookie) else {
+            return x::ModMask::empty();
+        };
+        let keycodes_per_modifier = reply.keycodes_per_modifier() as usize;
+        if keycodes_per_modifier == 0 {
+            return x::ModMask::empty();
+        }
+        // the keycodes are listed for shift, lock, control and mod1 to mod5 in that order
+        reply.keycodes().chunks(keycodes_per_modifier)
+            .position(|keycodes| keycodes.contains(&numlock))
+            .map_or(x::ModMask::empty(), |i| x::ModMask::from_bits_truncate(1 << i))
+    }
+
+    pub fn grab_key(&self, keycode: x::Keycode, modifiers: x::ModMask) {
+        let cookie = self.conn.send_request_checked(&x::GrabKey {
+            owner_events: true,
+            grab_window: self.screen.root(),
+            modifiers,
+            key: keycode,
+            pointer_mode: x::GrabMode::Async,
+            keyboard_mode: x::GrabMode::Async,
+        });
+        self.check_request(cookie);
+    }
+
+    pub fn grab_button(&self, button: x::ButtonIndex, modifiers: x::ModMask) {
+        let cookie = self.conn.send_request_checked(&x::GrabButton {
+            owner_events: false,
+            grab_window: self.screen.root(),
+            event_mask: x::EventMask::BUTTON_PRESS | x::EventMask::BUTTON_RELEASE | x::EventMask::POINTER_MOTION,
+            pointer_mode: x::GrabMode::Async,
+            keyboard_mode: x::GrabMode::Async,
+            confine_to: x::Window::none(),
+            cursor: x::Cursor::none(),
+            button,
+            modifiers,
+        });
+        self.check_request(cookie);
+    }
+
+    /// Grabs all buttons on an unfocused client so a click focuses it before being replayed to the client.
+    pub fn grab_focus_buttons(&self, window: x::Window) {
+        let cookie = self.conn.send_request_checked(&x::GrabButton {
+            owner_events: false,
+            grab_window: window,
+            event_mask: x::EventMask::BUTTON_PRESS,
+            pointer_mode: x::GrabMode::Sync,
+            keyboard_mode: x::GrabMode::Async,
+            confine_to: x::Window::none(),
+            cursor: x::Cursor::none(),
+            button: x::ButtonIndex::Any,
+            modifiers: x::ModMask::ANY,
+        });
+        self.check_request(cookie);
+    }
+
+    pub fn ungrab_focus_buttons(&self, window: x::Window) {
+        let cookie = self.conn.send_request_checked(&x::UngrabButton {
+            button: x::ButtonIndex::Any,
+            grab_window: window,
+            modifiers: x::ModMask::ANY,
+        });
+        self.check_request(cookie);
+    }
+
+    /// Releases the pointer frozen by a focus button grab and passes the click on to the client.
+    pub fn replay_pointer(&self, time: x::Timestamp) {
+        let cookie = self.conn.send_request_checked(&x::AllowEvents {
+            mode: x::Allow::ReplayPointer,
+            time,
+        });
+        self.check_request(cookie);
+    }
+
+    /// Moves the pointer to the center of a window unless it is already inside.
+    pub fn warp_pointer(&self, window: x::Window) {
+        let Some(rect) = self.get_window_geometry(window) else {
+            return;
+        };
+        let cookie = self.conn.send_request(&x::QueryPointer { window });
+        let Ok(pointer) = self.conn.wait_for_reply(cookie) else {
+            return;
+        };
+        if pointer.same_screen()
+            && (0..rect.width as i16).contains(&pointer.win_x())
+            && (0..rect.height as i16).contains(&pointer.win_y()) {
+            return;
+        }
+
+        let cookie = self.conn.send_request_checked(&x::WarpPointer {
+            src_window: x::Window::none(),
+            dst_window: window,
+            src_x: 0,
+            src_y: 0,
+            src_width: 0,
+            src_height: 0,
+            dst_x: (rect.width / 2) as i16,
+            dst_y: (rect.height / 2) as i16,
+        });
+        self.check_request(cookie);
+    }
+
+    pub fn raise_window(&self, window: x::Window) {
+        let cookie = self.conn.send_request_checked(&x::ConfigureWindow {
+            window,
+            value_list: &[x::ConfigWindow::StackMode(x::StackMode::Above)],
+        });
+        self.check_request(cookie);
+    }
+
+    pub fn ungrab_all_buttons(&self) {
+        let cookie = self.conn.send_request_checked(&x::UngrabButton {
+            button: x::ButtonIndex::Any,
+            grab_window: self.screen.root(),
+            modifiers: x::ModMask::ANY,
+        });
+        self.check_request(cookie);
+    }
+
+    pub fn ungrab_all_keys(&self) {
+        let cookie = self.conn.send_request_checked(&x::UngrabKey {
+            key: x::Grab::Any as x::Keycode,
+            grab_window: self.screen.root(),
+            modifiers: x::ModMask::ANY,
+        });
+        self.check_request(cookie);
+    }
+
+    pub fn select_client_events(&self, window: x::Window) {
         let cookie = self.conn.send_request_checked(&x::ChangeWindowAttributes {
             window,
             value_list: &[x::Cw::EventMask(x::EventMask::STRUCTURE_NOTIFY |
                                            x::EventMask::KEY_PRESS |
                                            x::EventMask::ENTER_WINDOW |
-                                           x::EventMask::FOCUS_CHANGE)],
+                                           x::EventMask::FOCUS_CHANGE |
+                                           x::EventMask::PROPERTY_CHANGE)],
         });
         self.check_request(cookie);
+    }
+
+    pub fn map_window(&self, window: x::Window) {
+        self.select_client_events(window);
         let cookie = self.conn.send_request_checked(&x::MapWindow {
             window,
         });
         self.check_request(cookie);
+        self.set_wm_state(window, WmState::Normal);
     }
 
+    /// Hides a managed window, dswm only unmaps clients when their workspace is not viewed.
     pub fn unmap_window(&self, window: x::Window) {
         let cookie = self.conn.send_request_checked(&x::UnmapWindow {
             window,
         });
         self.check_request(cookie);
+        self.set_wm_state(window, WmState::Iconic);
+    }
+
+    pub fn set_wm_state(&self, window: x::Window, state: WmState) {
+        let cookie = self.conn.send_request_checked(&x::ChangeProperty {
+            mode: x::PropMode::Replace,
+            window,
+            property: self.wm_atoms.wm_state,
+            r#type: self.wm_atoms.wm_state,
+            // no icon window
+            data: &[state as u32, 0],
+        });
+        self.check_request(cookie);
+    }
+
+    /// Marks a window as withdrawn once it is no longer managed.
+    pub fn remove_wm_state(&self, window: x::Window) {
+        let cookie = self.conn.send_request_checked(&x::DeleteProperty {
+            window,
+            property: self.wm_atoms.wm_state,
+        });
+        self.check_request(cookie);
     }
 
+    /// Focuses a client according to its ICCCM input model, given by the WM_HINTS input field and
+    /// WM_TAKE_FOCUS in WM_PROTOCOLS: No Input clients never get the focus, Passive ones get it
+    /// set directly, Globally Active ones are only asked to take it and Locally Active ones both.
+    /// The client becomes the active window in every model, No Input clients leave the X focus where it is.
     pub fn focus_window(&self, window: x::Window) {
+        let accepts_input = self.accepts_input(window);
+        let takes_focus = self.get_wm_protocols(window).contains(&self.wm_atoms.wm_take_focus);
+
+        if accepts_input {
+            let cookie = self.conn.send_request_checked(&x::SetInputFocus {
+                revert_to: x::InputFocus::PointerRoot,
+                focus: window,
+                time: CURRENT_TIME,
+            });
+            self.check_request(cookie);
+        }
+        if takes_focus {
+            self.send_event(window, self.wm_atoms.wm_take_focus);
+        }
+        self.set_active_window(window);
+    }
+
+    fn set_active_window(&self, window: x::Window) {
+        let cookie = self.conn.send_request_checked(&x::ChangeProperty {
+            mode: x::PropMode::Replace,
+            window: self.screen.root(),
+            property: self.net_atoms.net_active_window,
+            r#type: x::ATOM_WINDOW,
+            data: &[window],
+        });
+        self.check_request(cookie);
+    }
+
+    // clients without WM_HINTS or without the input flag set are assumed to want the focus
+    fn accepts_input(&self, window: x::Window) -> bool {
+        const INPUT_HINT: u32 = 1;
+        let cookie = self.conn.send_request(&x::GetProperty {
+            delete: false,
+            window,
+            property: x::ATOM_WM_HINTS,
+            r#type: x::ATOM_WM_HINTS,
+            long_offset: 0,
+            long_length: 9,
+        });
+        match self.conn.wait_for_reply(cookie) {
+            Ok(reply) if reply.format() == 32 => match reply.value::<u32>() {
+                [flags, input, ..] if flags & INPUT_HINT != 0 => *input != 0,
+                _ => true,
+            },
+            _ => true,
+        }
+    }
+
+    pub fn parse_client_message(&self, ev: &x::ClientMessageEvent) -> Option<ClientRequest> {
+        let x::ClientMessageData::Data32(data) = ev.data() else {
+            return None;
+        };
+
+        if ev.r#type() == self.net_atoms.net_wm_state {
+            let fullscreen = self.net_atoms.net_wm_state_fullscreen.resource_id();
+            if data[1] != fullscreen && data[2] != fullscreen {
+                return None;
+            }
+            let action = match data[0] {
+                0 => StateAction::Remove,
+                1 => StateAction::Add,
+                2 => StateAction::Toggle,
+                _ => return None,
+            };
+            return Some(ClientRequest::Fullscreen { window: ev.window(), action });
+        }
+        if ev.r#type() == self.net_atoms.net_current_desktop {
+            return Some(ClientRequest::CurrentDesktop { desktop: data[0] });
+        }
+        if ev.r#type() == self.net_atoms.net_wm_desktop {
+            return Some(ClientRequest::WindowDesktop { window: ev.window(), desktop: data[0] });
+        }
+        if ev.r#type() == self.net_atoms.net_active_window {
+            // source indication: 1 for applications, 2 for pagers and other user tools
+            return Some(ClientRequest::ActiveWindow { window: ev.window(), from_pager: data[0] == 2 });
+        }
+        if ev.r#type() == self.net_atoms.net_close_window {
+            return Some(ClientRequest::CloseWindow { window: ev.window() });
+        }
+        None
+    }
+
+    pub fn is_fullscreen_state(&self, window: x::Window) -> bool {
+        self.get_net_wm_state(window).contains(&self.net_atoms.net_wm_state_fullscreen)
+    }
+
+    /// Adds or removes _NET_WM_STATE_FULLSCREEN, other states set by the client or a pager are kept.
+    pub fn set_fullscreen_state(&self, window: x::Window, fullscreen: bool) {
+        let mut data = self.get_net_wm_state(window);
+        data.retain(|atom| *atom != self.net_atoms.net_wm_state_fullscreen);
+        if fullscreen {
+            data.push(self.net_atoms.net_wm_state_fullscreen);
+        }
+        let cookie = self.conn.send_request_checked(&x::ChangeProperty {
+            mode: x::PropMode::Replace,
+            window,
+            property: self.net_atoms.net_wm_state,
+            r#type: x::ATOM_ATOM,
+            data: &data,
+        });
+        self.check_request(cookie);
+    }
+
+    fn get_net_wm_state(&self, window: x::Window) -> Vec<x::Atom> {
+        let cookie = self.conn.send_request(&x::GetProperty {
+            delete: false,
+            window,
+            property: self.net_atoms.net_wm_state,
+            r#type: x::ATOM_ATOM,
+            long_offset: 0,
+            long_length: 32,
+        });
+        match self.conn.wait_for_reply(cookie) {
+            Ok(reply) if reply.format() == 32 => reply.value::<x::Atom>().to_vec(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Grants a ConfigureRequest of a window dswm does not manage as it was asked for.
+    pub fn configure_unmanaged(&self, request: &x::ConfigureRequestEvent) {
+        let mask = request.value_mask();
+        let mut value_list = Vec::new();
+        if mask.contains(x::ConfigWindowMask::X) {
+            value_list.push(x::ConfigWindow::X(request.x() as i32));
+        }
+        if mask.contains(x::ConfigWindowMask::Y) {
+            value_list.push(x::ConfigWindow::Y(request.y() as i32));
+        }
+        if mask.contains(x::ConfigWindowMask::WIDTH) {
+            value_list.push(x::ConfigWindow::Width(request.width() as u32));
+        }
+        if mask.contains(x::ConfigWindowMask::HEIGHT) {
+            value_list.push(x::ConfigWindow::Height(request.height() as u32));
+        }
+        if mask.contains(x::ConfigWindowMask::BORDER_WIDTH) {
+            value_list.push(x::ConfigWindow::BorderWidth(request.border_width() as u32));
+        }
+        if mask.contains(x::ConfigWindowMask::SIBLING) {
+            value_list.push(x::ConfigWindow::Sibling(request.sibling()));
+        }
+        if mask.contains(x::ConfigWindowMask::STACK_MODE) {
+            value_list.push(x::ConfigWindow::StackMode(request.stack_mode()));
+        }
+
+        let cookie = self.conn.send_request_checked(&x::ConfigureWindow {
+            window: request.window(),
+            value_list: &value_list,
+        });
+        self.check_request(cookie);
+    }
+
+    /// Tells a client its current geometry, in root coordinates, without moving it.
+    pub fn send_configure_notify(&self, window: x::Window, rect: Rect, border_width: u16) {
+        let event = x::ConfigureNotifyEvent::new(
+            window,
+            window,
+            x::Window::none(),
+            rect.x,
+            rect.y,
+            rect.width,
+            rect.height,
+            border_width,
+            false,
+        );
+        let cookie = self.conn.send_request_checked(&x::SendEvent {
+            propagate: false,
+            destination: x::SendEventDest::Window(window),
+            event_mask: x::EventMask::STRUCTURE_NOTIFY,
+            event: &event,
+        });
+        self.check_request(cookie);
+    }
+
+    /// Asks the client to close its window with WM_DELETE_WINDOW and kills it if it does not support the protocol.
+    /// Returns true if the client was asked, so the caller can kill it once it takes too long.
+    pub fn close_window(&self, window: x::Window) -> bool {
+        if !self.get_wm_protocols(window).contains(&self.wm_atoms.wm_delete_window) {
+            self.kill_client(window);
+            return false;
+        }
+        self.send_event(window, self.wm_atoms.wm_delete_window);
+        true
+    }
+
+    // the window may already be gone, so errors are not fatal here
+    pub fn kill_client(&self, window: x::Window) {
+        let cookie = self.conn.send_request_checked(&x::KillClient { resource: window.resource_id() });
+        if let Err(err) = self.conn.check_request(cookie) {
+            debug!("unable to kill client of {:?}: {err}", window);
+        }
+    }
+
+    fn get_wm_protocols(&self, window: x::Window) -> Vec<x::Atom> {
+        let cookie = self.conn.send_request(&x::GetProperty {
+            delete: false,
+            window,
+            property: self.wm_atoms.wm_protocols,
+            r#type: x::ATOM_ATOM,
+            long_offset: 0,
+            long_length: 32,
+        });
+        match self.conn.wait_for_reply(cookie) {
+            Ok(reply) if reply.format() == 32 => reply.value::<x::Atom>().to_vec(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Gives the keyboard focus back to the root so key bindings keep working when no client is focused.
+    pub fn focus_root(&self) {
         let cookie = self.conn.send_request_checked(&x::SetInputFocus {
-            revert_to: x::InputFocus::None,
-            focus: window,
+            revert_to: x::InputFocus::PointerRoot,
+            focus: self.screen.root(),
             time: CURRENT_TIME,
         });
         self.check_request(cookie);
+        self.set_active_window(x::Window::none());
+    }
+
+    /// Returns the top level windows a previous window manager left behind that should be managed:
+    /// viewable or iconic ones without override redirect. Transients come last so their parents are known.
+    pub fn get_existing_windows(&self) -> Vec<x::Window> {
+        let cookie = self.conn.send_request(&x::QueryTree {
+            window: self.screen.root(),
+        });
+        let Ok(tree) = self.conn.wait_for_reply(cookie) else {
+            return Vec::new();
+        };
+
+        let mut windows = Vec::new();
+        let mut transients = Vec::new();
+        for window in tree.children().iter().copied() {
+            let cookie = self.conn.send_request(&x::GetWindowAttributes { window });
+            let Ok(attributes) = self.conn.wait_for_reply(cookie) else {
+                continue;
+            };
+            let viewable = attributes.map_state() == x::MapState::Viewable;
+            if attributes.override_redirect() || !(viewable || self.get_wm_state(window) == Some(WmState::Iconic as u32)) {
+                continue;
+            }
+            if self.get_transient_for(window).is_some() {
+                transients.push(window);
+            }
+            else {
+                windows.push(window);
+            }
+        }
+        windows.extend(transients);
+        windows
+    }
+
+    fn get_wm_state(&self, window: x::Window) -> Option<u32> {
+        let cookie = self.conn.send_request(&x::GetProperty {
+            delete: false,
+            window,
+            property: self.wm_atoms.wm_state,
+            r#type: self.wm_atoms.wm_state,
+            long_offset: 0,
+            long_length: 2,
+        });
+        let reply = self.conn.wait_for_reply(cookie).ok()?;
+        if reply.format() != 32 {
+            return None;
+        }
+        reply.value::<u32>().first().copied()
+    }
+
+    /// Returns the geometry of a window relative to its parent, None if the window is already gone.
+    pub fn get_window_geometry(&self, window: x::Window) -> Option<Rect> {
+        let cookie = self.conn.send_request(&x::GetGeometry {
+            drawable: x::Drawable::Window(window),
+        });
+        let reply = self.conn.wait_for_reply(cookie).ok()?;
+        Some(Rect::new(reply.x(), reply.y(), reply.width(), reply.height()))
+    }
+
+    pub fn get_size_hints(&self, window: x::Window) -> SizeHints {
+        let cookie = self.conn.send_request(&x::GetProperty {
+            delete: false,
+            window,
+            property: x::ATOM_WM_NORMAL_HINTS,
+            r#type: x::ATOM_WM_SIZE_HINTS,
+            long_offset: 0,
+            long_length: 18,
+        });
+        match self.conn.wait_for_reply(cookie) {
+            Ok(reply) if reply.format() == 32 => SizeHints::from_property(reply.value::<u32>()),
+            _ => SizeHints::default(),
+        }
+    }
 
+    pub fn get_transient_for(&self, window: x::Window) -> Option<x::Window> {
+        let cookie = self.conn.send_request(&x::GetProperty {
+            delete: false,
+            window,
+            property: x::ATOM_WM_TRANSIENT_FOR,
+            r#type: x::ATOM_WINDOW,
+            long_offset: 0,
+            long_length: 1,
+        });
+        let reply = self.conn.wait_for_reply(cookie).ok()?;
+        if reply.format() != 32 {
+            return None;
+        }
+        reply.value::<x::Window>().first().copied().filter(|parent| !parent.is_none())
+    }
+
+    /// Checks _NET_WM_WINDOW_TYPE for dialogs, utility, splash and toolbar windows.
+    /// The types are listed in order of preference so the first known one decides.
+    pub fn is_floating_window_type(&self, window: x::Window) -> bool {
+        let cookie = self.conn.send_request(&x::GetProperty {
+            delete: false,
+            window,
+            property: self.net_atoms.net_wm_window_type,
+            r#type: x::ATOM_ATOM,
+            long_offset: 0,
+            long_length: 32,
+        });
+        let Ok(reply) = self.conn.wait_for_reply(cookie) else {
+            return false;
+        };
+        if reply.format() != 32 {
+            return false;
+        }
+
+        let floating_types = [
+            self.net_atoms.net_wm_window_type_dialog,
+            self.net_atoms.net_wm_window_type_utility,
+            self.net_atoms.net_wm_window_type_splash,
+            self.net_atoms.net_wm_window_type_toolbar,
+        ];
+        for window_type in reply.value::<x::Atom>() {
+            if *window_type == self.net_atoms.net_wm_window_type_normal {
+                return false;
+            }
+            if floating_types.contains(window_type) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Publishes the managed windows, in mapping order and bottom to top stacking order.
+    pub fn set_client_lists(&self, clients: &[x::Window], stacking: &[x::Window]) {
         let cookie = self.conn.send_request_checked(&x::ChangeProperty {
             mode: x::PropMode::Replace,
-            window,
-            property: self.net_atoms.net_active_window,
+            window: self.screen.root(),
+            property: self.net_atoms.net_client_list,
             r#type: x::ATOM_WINDOW,
-            data: &[window],
+            data: clients,
+        });
+        self.check_request(cookie);
+
+        let cookie = self.conn.send_request_checked(&x::ChangeProperty {
+            mode: x::PropMode::Replace,
+            window: self.screen.root(),
+            property: self.net_atoms.net_client_list_stacking,
+            r#type: x::ATOM_WINDOW,
+            data: stacking,
+        });
+        self.check_request(cookie);
+    }
+
+    /// Leaves state for the next dswm process on the root window.
+    pub fn set_saved_state(&self, state: &str) {
+        let cookie = self.conn.send_request_checked(&x::ChangeProperty {
+            mode: x::PropMode::Replace,
+            window: self.screen.root(),
+            property: self.wm_atoms.dswm_state,
+            r#type: x::ATOM_STRING,
+            data: state.as_bytes(),
+        });
+        self.check_request(cookie);
+    }
+
+    /// Reads and removes the state a previous dswm process left on the root window.
+    pub fn take_saved_state(&self) -> Option<String> {
+        let cookie = self.conn.send_request(&x::GetProperty {
+            delete: true,
+            window: self.screen.root(),
+            property: self.wm_atoms.dswm_state,
+            r#type: x::ATOM_STRING,
+            long_offset: 0,
+            long_length: u32::MAX / 4,
+        });
+        let reply = self.conn.wait_for_reply(cookie).ok()?;
+        if reply.format() != 8 {
+            return None;
+        }
+        Some(String::from_utf8_lossy(reply.value::<u8>()).into_owned())
+    }
+
+    /// Publishes the number, names and viewports of the desktops, one per workspace.
+    pub fn set_desktops(&self, names: &[String]) {
+        let cookie = self.conn.send_request_checked(&x::ChangeProperty {
+            mode: x::PropMode::Replace,
+            window: self.screen.root(),
+            property: self.net_atoms.net_number_of_desktops,
+            r#type: x::ATOM_CARDINAL,
+            data: &[names.len() as u32],
         });
         self.check_request(cookie);
 
-        self.send_event(window, self.wm_atoms.wm_take_focus,)
+        let mut name_data = Vec::new();
+        for name in names {
+            name_data.extend_from_slice(name.as_bytes());
+            name_data.push(0);
+        }
+        let cookie = self.conn.send_request_checked(&x::ChangeProperty {
+            mode: x::PropMode::Replace,
+            window: self.screen.root(),
+            property: self.net_atoms.net_desktop_names,
+            r#type: self.wm_atoms.utf8_string,
+            data: &name_data,
+        });
+        self.check_request(cookie);
+
+        // desktops don't scroll, every viewport is at the origin
+        let cookie = self.conn.send_request_checked(&x::ChangeProperty {
+            mode: x::PropMode::Replace,
+            window: self.screen.root(),
+            property: self.net_atoms.net_desktop_viewport,
+            r#type: x::ATOM_CARDINAL,
+            data: &vec![0u32; names.len() * 2],
+        });
+        self.check_request(cookie);
+    }
+
+    pub fn set_current_desktop(&self, desktop: u32) {
+        let cookie = self.conn.send_request_checked(&x::ChangeProperty {
+            mode: x::PropMode::Replace,
+            window: self.screen.root(),
+            property: self.net_atoms.net_current_desktop,
+            r#type: x::ATOM_CARDINAL,
+            data: &[desktop],
+        });
+        self.check_request(cookie);
+    }
 
+    pub fn set_window_desktop(&self, window: x::Window, desktop: u32) {
+        let cookie = self.conn.send_request_checked(&x::ChangeProperty {
+            mode: x::PropMode::Replace,
+            window,
+            property: self.net_atoms.net_wm_desktop,
+            r#type: x::ATOM_CARDINAL,
+            data: &[desktop],
+        });
+        self.check_request(cookie);
+    }
 
+    pub fn set_border_color(&self, window: x::Window, color: u32) {
+        let cookie = self.conn.send_request_checked(&x::ChangeWindowAttributes {
+            window,
+            value_list: &[x::Cw::BorderPixel(color)],
+        });
+        self.check_request(cookie);
     }
 
     pub fn set_window_size(&self, window: x::Window, width: u32, height: u32) {
@@ -200,7 +996,7 @@ impl Xmanager {
         let event = x::ClientMessageEvent::new(
             window,
             self.wm_atoms.wm_protocols,
-            x::ClientMessageData::Data32([event_atom.resource_id() as u32, CURRENT_TIME, 0, 0, 0])
+            x::ClientMessageData::Data32([event_atom.resource_id(), CURRENT_TIME, 0, 0, 0])
 
             );
         self.conn.send_request(&x::SendEvent {
@@ -263,10 +1059,15 @@ impl Xmanager {
     }
 
     fn check_request(&self, cookie: xcb::VoidCookieChecked) {
-        self.conn.check_request(cookie).unwrap_or_else(|err| {
-            error!("X error: {err}");
-            process::exit(1);
-        });
+        match self.conn.check_request(cookie) {
+            Ok(()) => {}
+            // the window got destroyed before its DestroyNotify was handled
+            Err(xcb::ProtocolError::X(x::Error::Window(err), _)) => debug!("ignoring BadWindow: {:?}", err),
+            Err(err) => {
+                error!("X error: {err}");
+                process::exit(1);
+            }
+        }
     }
 
     fn setup_atoms(conn: &Connection) -> (WmAtoms, NetAtoms)  {
@@ -312,7 +1113,7 @@ impl Xmanager {
         }),
         conn.send_request(&x::InternAtom {
             only_if_exists: false,
-            name: b"_NET_WM_STATE_WINDOW_TYPE",
+            name: b"_NET_WM_WINDOW_TYPE",
         }),
         conn.send_request(&x::InternAtom {
             only_if_exists: false,
@@ -321,23 +1122,88 @@ impl Xmanager {
         conn.send_request(&x::InternAtom {
             only_if_exists: false,
             name: b"_NET_CLIENT_LIST",
+        }),
+        conn.send_request(&x::InternAtom {
+            only_if_exists: false,
+            name: b"_NET_WM_WINDOW_TYPE_UTILITY",
+        }),
+        conn.send_request(&x::InternAtom {
+            only_if_exists: false,
+            name: b"_NET_WM_WINDOW_TYPE_SPLASH",
+        }),
+        conn.send_request(&x::InternAtom {
+            only_if_exists: false,
+            name: b"_NET_WM_WINDOW_TYPE_TOOLBAR",
+        }),
+        conn.send_request(&x::InternAtom {
+            only_if_exists: false,
+            name: b"_NET_WM_WINDOW_TYPE_NORMAL",
+        }),
+        conn.send_request(&x::InternAtom {
+            only_if_exists: false,
+            name: b"_NET_CLIENT_LIST_STACKING",
+        }),
+        conn.send_request(&x::InternAtom {
+            only_if_exists: false,
+            name: b"_NET_NUMBER_OF_DESKTOPS",
+        }),
+        conn.send_request(&x::InternAtom {
+            only_if_exists: false,
+            name: b"_NET_DESKTOP_NAMES",
+        }),
+        conn.send_request(&x::InternAtom {
+            only_if_exists: false,
+            name: b"_NET_CURRENT_DESKTOP",
+        }),
+        conn.send_request(&x::InternAtom {
+            only_if_exists: false,
+            name: b"_NET_WM_DESKTOP",
+        }),
+        conn.send_request(&x::InternAtom {
+            only_if_exists: false,
+            name: b"_NET_DESKTOP_VIEWPORT",
+        }),
+        conn.send_request(&x::InternAtom {
+            only_if_exists: false,
+            name: b"UTF8_STRING",
+        }),
+        conn.send_request(&x::InternAtom {
+            only_if_exists: false,
+            name: b"_NET_CLOSE_WINDOW",
+        }),
+        conn.send_request(&x::InternAtom {
+            only_if_exists: false,
+            name: b"_DSWM_STATE",
         }));
         (WmAtoms {
-            wm_protocols:              conn.wait_for_reply(cookies.0).unwrap().atom(),
-            wm_delete_window:          conn.wait_for_reply(cookies.1).unwrap().atom(),
-            wm_state:                  conn.wait_for_reply(cookies.2).unwrap().atom(),
-            wm_take_focus:             conn.wait_for_reply(cookies.3).unwrap().atom(),
+            wm_protocols:               conn.wait_for_reply(cookies.0).unwrap().atom(),
+            wm_delete_window:           conn.wait_for_reply(cookies.1).unwrap().atom(),
+            wm_state:                   conn.wait_for_reply(cookies.2).unwrap().atom(),
+            wm_take_focus:              conn.wait_for_reply(cookies.3).unwrap().atom(),
+            utf8_string:                conn.wait_for_reply(cookies.23).unwrap().atom(),
+            dswm_state:                 conn.wait_for_reply(cookies.25).unwrap().atom(),
         },
         NetAtoms {
-            net_active_window:         conn.wait_for_reply(cookies.4).unwrap().atom(),
-            net_supported:             conn.wait_for_reply(cookies.5).unwrap().atom(),
-            net_wm_name:               conn.wait_for_reply(cookies.6).unwrap().atom(),
-            net_wm_state:              conn.wait_for_reply(cookies.7).unwrap().atom(),
-            net_supporting_wm_check:   conn.wait_for_reply(cookies.8).unwrap().atom(),
-            net_wm_state_fullscreen:   conn.wait_for_reply(cookies.9).unwrap().atom(),
-            net_wm_state_window_type:  conn.wait_for_reply(cookies.10).unwrap().atom(),
-            net_wm_window_type_dialog: conn.wait_for_reply(cookies.11).unwrap().atom(),
-            net_client_list:           conn.wait_for_reply(cookies.12).unwrap().atom(),
+            net_active_window:          conn.wait_for_reply(cookies.4).unwrap().atom(),
+            net_supported:              conn.wait_for_reply(cookies.5).unwrap().atom(),
+            net_wm_name:                conn.wait_for_reply(cookies.6).unwrap().atom(),
+            net_wm_state:               conn.wait_for_reply(cookies.7).unwrap().atom(),
+            net_supporting_wm_check:    conn.wait_for_reply(cookies.8).unwrap().atom(),
+            net_wm_state_fullscreen:    conn.wait_for_reply(cookies.9).unwrap().atom(),
+            net_wm_window_type:         conn.wait_for_reply(cookies.10).unwrap().atom(),
+            net_wm_window_type_dialog:  conn.wait_for_reply(cookies.11).unwrap().atom(),
+            net_client_list:            conn.wait_for_reply(cookies.12).unwrap().atom(),
+            net_wm_window_type_utility: conn.wait_for_reply(cookies.13).unwrap().atom(),
+            net_wm_window_type_splash:  conn.wait_for_reply(cookies.14).unwrap().atom(),
+            net_wm_window_type_toolbar: conn.wait_for_reply(cookies.15).unwrap().atom(),
+            net_wm_window_type_normal:  conn.wait_for_reply(cookies.16).unwrap().atom(),
+            net_client_list_stacking:   conn.wait_for_reply(cookies.17).unwrap().atom(),
+            net_number_of_desktops:     conn.wait_for_reply(cookies.18).unwrap().atom(),
+            net_desktop_names:          conn.wait_for_reply(cookies.19).unwrap().atom(),
+            net_current_desktop:        conn.wait_for_reply(cookies.20).unwrap().atom(),
+            net_wm_desktop:             conn.wait_for_reply(cookies.21).unwrap().atom(),
+            net_desktop_viewport:       conn.wait_for_reply(cookies.22).unwrap().atom(),
+            net_close_window:           conn.wait_for_reply(cookies.24).unwrap().atom(),
         })
     }
 }